@@ -30,6 +30,13 @@ impl Aabb {
     pub fn get_max(&self) -> Point3d {
         self.max
     }
+    pub fn get_surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            return 0.0; // empty box
+        }
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
 
     fn get_superset(&self, other: Self) -> Self {
         Aabb::from_points(
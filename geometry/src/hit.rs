@@ -0,0 +1,66 @@
+use crate::ray::Ray3d;
+use crate::{Point3d, Vector3d};
+
+/// Everything known about the place where a ray hits a primitive
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct HitRecord {
+    pub distance: f32,
+    pub point: Point3d,
+    /// Geometric normal, pointing outwards of the primitive regardless of the ray's direction
+    pub normal: Vector3d,
+    /// Normal to shade with, interpolated from vertex normals where the primitive has them. Same
+    /// side of the surface as the geometric normal.
+    pub shading_normal: Vector3d,
+    /// Barycentric coordinates for triangles, spherical ones for spheres
+    pub u: f32,
+    pub v: f32,
+    /// Where to sample textures, interpolated from the vertices where the primitive has them,
+    /// (u, v) otherwise
+    pub tex_coords: [f32; 2],
+    /// Tangent and bitangent sign for normal mapping, where the primitive has them
+    pub tangent: Option<(Vector3d, f32)>,
+    /// Whether the ray hits the outer side of the surface
+    pub front_face: bool,
+    /// Index of the primitive, filled in by the acceleration structure that found the hit
+    pub primitive_id: usize,
+    /// Time of the ray, the rays spawned from the hit carry it on
+    pub time: f32,
+}
+
+impl HitRecord {
+    pub fn new(ray: &Ray3d, distance: f32, normal: Vector3d, u: f32, v: f32) -> HitRecord {
+        HitRecord {
+            distance,
+            point: ray.get_point_at(distance),
+            normal,
+            shading_normal: normal,
+            u,
+            v,
+            tex_coords: [u, v],
+            tangent: None,
+            front_face: ray.get_direction() * normal < 0.0,
+            primitive_id: 0,
+            time: ray.get_time(),
+        }
+    }
+
+    pub fn with_shading_normal(mut self, shading_normal: Vector3d) -> HitRecord {
+        self.shading_normal = shading_normal;
+        self
+    }
+
+    pub fn with_tex_coords(mut self, tex_coords: [f32; 2]) -> HitRecord {
+        self.tex_coords = tex_coords;
+        self
+    }
+
+    pub fn with_tangent(mut self, tangent: Option<(Vector3d, f32)>) -> HitRecord {
+        self.tangent = tangent;
+        self
+    }
+
+    pub fn with_primitive_id(mut self, primitive_id: usize) -> HitRecord {
+        self.primitive_id = primitive_id;
+        self
+    }
+}
@@ -1,4 +1,6 @@
+pub use hit::HitRecord;
 pub use matrix::Mat4f;
+pub use motion::{Lerp, Motion};
 pub use point::{Point3d, Point4d};
 pub use traceable::PrimitiveType;
 pub use traceable::TraceablePrimitive;
@@ -10,7 +12,10 @@ use crate::sphere::Sphere;
 use crate::triangle::Triangle;
 
 pub mod aabb;
+pub mod hit;
 pub mod matrix;
+mod mikktspace;
+pub mod motion;
 pub mod point;
 pub mod ray;
 pub mod sphere;
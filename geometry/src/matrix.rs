@@ -1,4 +1,4 @@
-use crate::Point4d;
+use crate::{Point4d, Vector3d};
 
 pub struct Mat4f {
     pub raw: [[f32; 4]; 4],
@@ -21,6 +21,36 @@ impl Mat4f {
     pub fn from_rows(a: [f32; 4], b: [f32; 4], c: [f32; 4], d: [f32; 4]) -> Self {
         Mat4f { raw: [a, b, c, d] }
     }
+
+    /// Transforms a direction, e.g. a tangent, ignoring the translation. The result is normalized.
+    pub fn transform_direction(&self, dir: &Vector3d) -> Vector3d {
+        let row = |i: usize| Vector3d::from_coords(self.raw[i][0], self.raw[i][1], self.raw[i][2]);
+        Vector3d::from_coords(row(0) * *dir, row(1) * *dir, row(2) * *dir).normalize()
+    }
+
+    /// Whether the transformation mirrors the space, turning right-handed bases into left-handed
+    /// ones
+    pub fn is_mirroring(&self) -> bool {
+        let row = |i: usize| Vector3d::from_coords(self.raw[i][0], self.raw[i][1], self.raw[i][2]);
+        row(0) * row(1).crossprod(&row(2)) < 0.0
+    }
+
+    /// Cube root of the volume change of the upper 3x3 part, the scale factor of uniform scalings
+    pub fn get_uniform_scale(&self) -> f32 {
+        let row = |i: usize| Vector3d::from_coords(self.raw[i][0], self.raw[i][1], self.raw[i][2]);
+        (row(0) * row(1).crossprod(&row(2))).abs().cbrt()
+    }
+
+    /// Transforms a surface normal so that it stays perpendicular to the transformed surface, i.e.
+    /// by the inverse transpose of the upper 3x3 part. The result is normalized.
+    pub fn transform_normal(&self, normal: &Vector3d) -> Vector3d {
+        let row = |i: usize| Vector3d::from_coords(self.raw[i][0], self.raw[i][1], self.raw[i][2]);
+        let (a, b, c) = (row(0), row(1), row(2));
+        // Rows of the cofactor matrix, the inverse transpose times the determinant
+        let (bc, ca, ab) = (b.crossprod(&c), c.crossprod(&a), a.crossprod(&b));
+        let det_sign = (a * bc).signum();
+        Vector3d::from_coords(bc * *normal, ca * *normal, ab * *normal).normalize() * det_sign
+    }
     pub fn rotate_about_x(&self, angle_deg: f32) -> Self {
         let sin = angle_deg.to_radians().sin();
         let cos = angle_deg.to_radians().cos();
@@ -0,0 +1,255 @@
+// Port of Morten S. Mikkelsen's MikkTSpace tangent generation, the reference implementation used
+// by Blender, Substance and most other bakers. Normal maps baked against its tangents only shade
+// right with exactly the same ones, so this follows mikktspace.c step by step:
+//
+// 1. Vertices with the same position, normal and texture coordinates are welded together
+// 2. Every triangle gets its directions of growing u and v and whether its texture mapping keeps
+//    the orientation, i.e. is not mirrored
+// 3. The corners around each welded vertex are split into groups: corners of triangles connected
+//    through shared edges and having the same orientation. Triangles with degenerate texture
+//    coordinates join any group and take on its orientation.
+// 4. The tangent of a group is the average of the u directions of its triangles, projected onto
+//    the plane of the vertex normal and weighted by the angles of the triangles at the vertex
+// 5. Triangles with repeated vertices get the tangents of the other corners at the same vertex
+//
+// Only the default settings are ported: no angular threshold splitting a group further, and
+// triangles only, as the meshes are triangulated before this runs.
+
+use std::collections::HashMap;
+
+use crate::triangle::Triangle;
+use crate::{Point3d, Vector3d};
+
+// Per-corner results, (tangent, bitangent sign)
+pub(crate) type CornerTangents = [(Vector3d, f32); 3];
+
+// fabsf(x) > FLT_MIN in the reference implementation
+fn is_not_zero(x: f32) -> bool {
+    x.abs() > f32::MIN_POSITIVE
+}
+
+fn normalize_if_not_zero(v: Vector3d) -> Vector3d {
+    if is_not_zero(v.x) || is_not_zero(v.y) || is_not_zero(v.z) {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+// Any unit vector perpendicular to the normal, for the corners nothing can be derived for
+fn get_any_tangent(normal: Vector3d) -> Vector3d {
+    let axis = if normal.x.abs() < 0.9 {
+        Vector3d::from_coords(1.0, 0.0, 0.0)
+    } else {
+        Vector3d::from_coords(0.0, 1.0, 0.0)
+    };
+    normalize_if_not_zero(normal.crossprod(&axis))
+}
+
+struct Face {
+    // Index into the triangles
+    tri_idx: usize,
+    // Welded vertex indices of the corners
+    verts: [usize; 3],
+    positions: [Point3d; 3],
+    normals: [Vector3d; 3],
+    // Unit direction of growing u
+    os: Vector3d,
+    orient_preserving: bool,
+    // Degenerate texture coordinates, the triangle joins whatever group it meets
+    group_with_any: bool,
+    // Two or three corners at the same vertex, left out of the grouping
+    degenerate: bool,
+    // Faces across the edges starting at each corner
+    neighbors: [Option<usize>; 3],
+    // Groups the corners belong to
+    groups: [Option<usize>; 3],
+}
+
+struct Group {
+    vertex: usize,
+    orient_preserving: bool,
+    faces: Vec<usize>,
+}
+
+impl Face {
+    fn new(tri_idx: usize, tri: &Triangle, verts: [usize; 3]) -> Self {
+        let t = tri.tex_coords.unwrap();
+        let normals = tri.vertex_normals.unwrap_or([tri.normal; 3]);
+        let (d1, d2) = (tri.v[1] - tri.v[0], tri.v[2] - tri.v[0]);
+        let (t21x, t21y) = (t[1][0] - t[0][0], t[1][1] - t[0][1]);
+        let (t31x, t31y) = (t[2][0] - t[0][0], t[2][1] - t[0][1]);
+        let signed_area = t21x * t31y - t21y * t31x;
+        let orient_preserving = signed_area > 0.0;
+
+        // Scaled by the signed area, which turns them around for mirrored mappings
+        let os = d1 * t31y - d2 * t21y;
+        let ot = d1 * -t31x + d2 * t21x;
+        let abs_area = signed_area.abs();
+        let group_with_any = !(is_not_zero(signed_area)
+            && is_not_zero(os.len() / abs_area)
+            && is_not_zero(ot.len() / abs_area));
+        let sign = if orient_preserving { 1.0 } else { -1.0 };
+
+        Face {
+            tri_idx,
+            verts,
+            positions: tri.v,
+            normals,
+            os: normalize_if_not_zero(os) * sign,
+            orient_preserving,
+            group_with_any,
+            degenerate: verts[0] == verts[1] || verts[0] == verts[2] || verts[1] == verts[2],
+            neighbors: [None; 3],
+            groups: [None; 3],
+        }
+    }
+
+    fn get_corner(&self, vertex: usize) -> usize {
+        self.verts.iter().position(|&x| x == vertex).unwrap()
+    }
+}
+
+/// Tangents of the corners of the triangles with texture coordinates, None for the others
+pub(crate) fn generate_tangents(triangles: &[Triangle]) -> Vec<Option<CornerTangents>> {
+    let mut faces = weld_vertices(triangles);
+    find_neighbors(&mut faces);
+    let groups = build_groups(&mut faces);
+
+    let mut tangents: Vec<Option<CornerTangents>> = vec![None; triangles.len()];
+    for face in &faces {
+        tangents[face.tri_idx] = Some([0, 1, 2].map(|i| (get_any_tangent(face.normals[i]), 1.0)));
+    }
+    for (group_idx, group) in groups.iter().enumerate() {
+        let tangent = eval_tangent(&faces, group);
+        let sign = if group.orient_preserving { 1.0 } else { -1.0 };
+        for &face_idx in &group.faces {
+            let face = &faces[face_idx];
+            let corner = face.groups.iter().position(|&x| x == Some(group_idx)).unwrap();
+            tangents[face.tri_idx].as_mut().unwrap()[corner] = (tangent, sign);
+        }
+    }
+
+    // Corners of the degenerate triangles copy from a grouped corner at the same vertex
+    let mut by_vertex: HashMap<usize, (Vector3d, f32)> = HashMap::new();
+    for face in faces.iter().filter(|x| !x.degenerate) {
+        for corner in 0..3 {
+            if face.groups[corner].is_some() {
+                let tangent = tangents[face.tri_idx].unwrap()[corner];
+                by_vertex.entry(face.verts[corner]).or_insert(tangent);
+            }
+        }
+    }
+    for face in faces.iter().filter(|x| x.degenerate) {
+        for corner in 0..3 {
+            if let Some(&tangent) = by_vertex.get(&face.verts[corner]) {
+                tangents[face.tri_idx].as_mut().unwrap()[corner] = tangent;
+            }
+        }
+    }
+    tangents
+}
+
+fn weld_vertices(triangles: &[Triangle]) -> Vec<Face> {
+    let mut vertices: HashMap<[u32; 8], usize> = HashMap::new();
+    let mut faces = Vec::new();
+    for (tri_idx, tri) in triangles.iter().enumerate() {
+        let Some(t) = tri.tex_coords else {
+            continue;
+        };
+        let normals = tri.vertex_normals.unwrap_or([tri.normal; 3]);
+        let verts = [0, 1, 2].map(|i| {
+            let (p, n) = (tri.v[i], normals[i]);
+            let key = [p.x, p.y, p.z, n.x, n.y, n.z, t[i][0], t[i][1]].map(|x| x.to_bits());
+            let next_idx = vertices.len();
+            *vertices.entry(key).or_insert(next_idx)
+        });
+        faces.push(Face::new(tri_idx, tri, verts));
+    }
+    faces
+}
+
+// Faces sharing an edge wound the opposite way, i.e. with the same facing
+fn find_neighbors(faces: &mut [Face]) {
+    let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+    for (face_idx, face) in faces.iter().enumerate().filter(|(_, x)| !x.degenerate) {
+        for corner in 0..3 {
+            let edge = (face.verts[corner], face.verts[(corner + 1) % 3]);
+            edges.entry(edge).or_insert(face_idx);
+        }
+    }
+    for face in faces.iter_mut().filter(|x| !x.degenerate) {
+        for corner in 0..3 {
+            let reversed = (face.verts[(corner + 1) % 3], face.verts[corner]);
+            face.neighbors[corner] = edges.get(&reversed).copied();
+        }
+    }
+}
+
+// Build4RuleGroups() and AssignRecur() of the reference implementation, without the recursion
+fn build_groups(faces: &mut [Face]) -> Vec<Group> {
+    let mut groups: Vec<Group> = Vec::new();
+    for face_idx in 0..faces.len() {
+        for corner in 0..3 {
+            let face = &faces[face_idx];
+            if face.degenerate || face.group_with_any || face.groups[corner].is_some() {
+                continue;
+            }
+            let group_idx = groups.len();
+            let (vertex, orient_preserving) = (face.verts[corner], face.orient_preserving);
+            groups.push(Group { vertex, orient_preserving, faces: Vec::new() });
+
+            let mut stack = vec![(face_idx, corner)];
+            while let Some((face_idx, corner)) = stack.pop() {
+                let face = &mut faces[face_idx];
+                if face.groups[corner].is_some() {
+                    continue;
+                }
+                // The first group to reach a triangle with degenerate texture coordinates decides
+                // its orientation
+                if face.group_with_any && face.groups.iter().all(|x| x.is_none()) {
+                    face.orient_preserving = orient_preserving;
+                }
+                if face.orient_preserving != orient_preserving {
+                    continue;
+                }
+                face.groups[corner] = Some(group_idx);
+                groups[group_idx].faces.push(face_idx);
+
+                // Across the edges ending and starting at the corner
+                let neighbors = [face.neighbors[(corner + 2) % 3], face.neighbors[corner]];
+                for neighbor_idx in neighbors.iter().flatten().copied() {
+                    stack.push((neighbor_idx, faces[neighbor_idx].get_corner(vertex)));
+                }
+            }
+        }
+    }
+    groups
+}
+
+// EvalTspace() of the reference implementation
+fn eval_tangent(faces: &[Face], group: &Group) -> Vector3d {
+    let mut sum = Vector3d::new();
+    let mut normal = None;
+    for face in group.faces.iter().map(|&x| &faces[x]) {
+        let corner = face.get_corner(group.vertex);
+        let n = face.normals[corner];
+        normal.get_or_insert(n);
+        if face.group_with_any {
+            continue;
+        }
+        let project = |v: Vector3d| normalize_if_not_zero(v - n * (n * v));
+        let os = project(face.os);
+
+        let p = face.positions[corner];
+        let v1 = project(face.positions[(corner + 2) % 3] - p);
+        let v2 = project(face.positions[(corner + 1) % 3] - p);
+        let angle = (v1 * v2).clamp(-1.0, 1.0).acos();
+        sum = sum + os * angle;
+    }
+    let tangent = normalize_if_not_zero(sum);
+    match normal {
+        Some(n) if tangent == Vector3d::new() => get_any_tangent(n),
+        _ => tangent,
+    }
+}
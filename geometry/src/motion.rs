@@ -0,0 +1,212 @@
+use crate::aabb::Aabb;
+use crate::hit::HitRecord;
+use crate::ray::Ray3d;
+use crate::sphere::Sphere;
+use crate::traceable::TraceablePrimitive;
+use crate::triangle::Triangle;
+use crate::{Mat4f, Point3d, Vector3d};
+
+/// Primitives that can be blended between two of their states
+pub trait Lerp {
+    /// self for t = 0.0, other for t = 1.0
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+/// Primitive moving over the shutter interval, at the time of the ray it is linearly interpolated
+/// between its states at the start and the end. Bounded by both the states, which bounds all the
+/// ones in between too, so the acceleration structures need no changes for it.
+#[derive(Copy, Clone)]
+pub struct Motion<P> {
+    pub start: P,
+    pub end: P,
+}
+
+impl<P: Lerp> Motion<P> {
+    pub fn new(start: P, end: P) -> Self {
+        Motion { start, end }
+    }
+
+    /// The primitive as it is at the time, clamped to the shutter interval
+    pub fn get_at(&self, time: f32) -> P {
+        self.start.lerp(&self.end, time.clamp(0.0, 1.0))
+    }
+}
+
+impl<P: Lerp + TraceablePrimitive> Motion<P> {
+    /// Normal at a point on the surface of the primitive as it is at the time
+    pub fn get_normal_at(&self, surface_pt: &Point3d, time: f32) -> Vector3d {
+        self.get_at(time).get_normal(surface_pt)
+    }
+}
+
+impl<P: Lerp + TraceablePrimitive> TraceablePrimitive for Motion<P> {
+    fn get_distance_to(&self, ray: &Ray3d) -> Option<f32> {
+        self.get_at(ray.get_time()).get_distance_to(ray)
+    }
+
+    fn intersect(&self, ray: &Ray3d) -> Option<HitRecord> {
+        self.get_at(ray.get_time()).intersect(ray)
+    }
+
+    // Without a time this can only be the normal at the start of the shutter interval, the hits
+    // carry the one at their time and get_normal_at() gives it for any other
+    fn get_normal(&self, surface_pt: &Point3d) -> Vector3d {
+        self.get_normal_at(surface_pt, 0.0)
+    }
+
+    fn get_bounding_box(&self) -> Aabb {
+        self.start.get_bounding_box() + self.end.get_bounding_box()
+    }
+
+    fn get_centroid(&self) -> Point3d {
+        lerp_point(self.start.get_centroid(), self.end.get_centroid(), 0.5)
+    }
+
+    fn model_to_world(&self, model: &Mat4f) -> Self {
+        Motion {
+            start: self.start.model_to_world(model),
+            end: self.end.model_to_world(model),
+        }
+    }
+}
+
+impl Lerp for Triangle {
+    // Vertices move along straight lines, rotations are only followed closely for small angles
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let v = [0, 1, 2].map(|i| lerp_point(self.v[i], other.v[i], t));
+        let tri = Triangle::new(v[0], v[1], v[2]);
+        Triangle {
+            vertex_normals: self
+                .vertex_normals
+                .zip(other.vertex_normals)
+                .map(|(a, b)| [0, 1, 2].map(|i| lerp_vector(a[i], b[i], t).normalize())),
+            tex_coords: self.tex_coords,
+            vertex_tangents: self
+                .vertex_tangents
+                .zip(other.vertex_tangents)
+                .map(|(a, b)| {
+                    [0, 1, 2].map(|i| (lerp_vector(a[i].0, b[i].0, t).normalize(), a[i].1))
+                }),
+            ..tri
+        }
+    }
+}
+
+impl Lerp for Sphere {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Sphere::new(
+            lerp_point(self.center, other.center, t),
+            self.radius + (other.radius - self.radius) * t,
+        )
+    }
+}
+
+fn lerp_point(a: Point3d, b: Point3d, t: f32) -> Point3d {
+    a + (b - a) * t
+}
+
+fn lerp_vector(a: Vector3d, b: Vector3d, t: f32) -> Vector3d {
+    a * (1.0 - t) + b * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traceable::PrimitiveType;
+
+    fn get_moving_sphere() -> Motion<Sphere> {
+        // Sweeps from x = -1 to x = 1, growing from radius 0.5 to 1.0
+        Motion::new(
+            Sphere::new(Point3d::from_coords(-1.0, 0.0, -5.0), 0.5),
+            Sphere::new(Point3d::from_coords(1.0, 0.0, -5.0), 1.0),
+        )
+    }
+
+    fn get_ray(x: f32, time: f32) -> Ray3d {
+        Ray3d::from(Point3d::from_coords(x, 0.0, 0.0), Vector3d::from_coords(0.0, 0.0, -1.0))
+            .with_time(time)
+    }
+
+    #[test]
+    fn t_moving_sphere() {
+        let sphere = get_moving_sphere();
+        for (time, x, radius) in [(0.0, -1.0, 0.5), (0.5, 0.0, 0.75), (1.0, 1.0, 1.0)] {
+            let hit = sphere.intersect(&get_ray(x, time)).unwrap();
+            assert!((hit.distance - (5.0 - radius)).abs() < 1e-5);
+            assert!((hit.normal - Vector3d::from_coords(0.0, 0.0, 1.0)).len() < 1e-5);
+            assert_eq!(hit.time, time);
+            assert_eq!(sphere.get_distance_to(&get_ray(x, time)), Some(hit.distance));
+        }
+        // Where the sphere was at the start is empty by the end
+        assert!(sphere.intersect(&get_ray(-1.0, 1.0)).is_none());
+        assert!(sphere.intersect(&get_ray(1.0, 0.0)).is_none());
+        // Clamped to the shutter interval
+        assert!(sphere.intersect(&get_ray(-1.0, -0.5)).is_some());
+        assert!(sphere.intersect(&get_ray(1.0, 1.5)).is_some());
+
+        let bbox = sphere.get_bounding_box();
+        assert_eq!(bbox.get_min(), Point3d::from_coords(-1.5, -1.0, -6.0));
+        assert_eq!(bbox.get_max(), Point3d::from_coords(2.0, 1.0, -4.0));
+    }
+
+    #[test]
+    fn t_moving_normal() {
+        let sphere = get_moving_sphere();
+        // Above the center of the sphere at the middle of the interval
+        let surface_pt = Point3d::from_coords(0.0, 0.75, -5.0);
+        let at_middle = sphere.get_normal_at(&surface_pt, 0.5);
+        assert!((at_middle - Vector3d::from_coords(0.0, 1.0, 0.0)).len() < 1e-5);
+        // Only valid at the start without the time
+        let surface_pt = Point3d::from_coords(-1.0, 0.5, -5.0);
+        let at_start = sphere.get_normal(&surface_pt);
+        assert!((at_start - Vector3d::from_coords(0.0, 1.0, 0.0)).len() < 1e-5);
+        assert_eq!(at_start, sphere.get_normal_at(&surface_pt, 0.0));
+    }
+
+    #[test]
+    fn t_primitive_type_moving() {
+        let sphere = get_moving_sphere();
+        let moving = PrimitiveType::moving(
+            PrimitiveType::Sphere(sphere.start),
+            PrimitiveType::Sphere(sphere.end),
+        );
+        assert!(matches!(moving, PrimitiveType::MovingSphere(_)));
+        assert_eq!(
+            moving.intersect(&get_ray(0.0, 0.5)).map(|x| x.distance),
+            sphere.intersect(&get_ray(0.0, 0.5)).map(|x| x.distance)
+        );
+
+        let triangle = Triangle::new(
+            Point3d::from_coords(-1.0, -1.0, -5.0),
+            Point3d::from_coords(1.0, -1.0, -5.0),
+            Point3d::from_coords(0.0, 1.0, -5.0),
+        );
+        let offset = Vector3d::from_coords(3.0, 0.0, 0.0);
+        let moved = Triangle::new(
+            triangle.v[0] + offset,
+            triangle.v[1] + offset,
+            triangle.v[2] + offset,
+        );
+        let moving = PrimitiveType::moving(
+            PrimitiveType::Triangle(triangle),
+            PrimitiveType::Triangle(moved),
+        );
+        assert!(matches!(moving, PrimitiveType::MovingTriangle(_)));
+        assert!(moving.intersect(&get_ray(0.0, 0.0)).is_some());
+        assert!(moving.intersect(&get_ray(0.0, 1.0)).is_none());
+        assert!(moving.intersect(&get_ray(3.0, 1.0)).is_some());
+        assert!(moving.intersect(&get_ray(1.5, 0.5)).is_some());
+
+        // Primitives staying in place, or changing kind, are left as they are
+        let still = PrimitiveType::moving(
+            PrimitiveType::Triangle(triangle),
+            PrimitiveType::Triangle(triangle),
+        );
+        assert!(matches!(still, PrimitiveType::Triangle(_)));
+        let still = PrimitiveType::Sphere(sphere.start);
+        assert!(matches!(PrimitiveType::moving(still, still), PrimitiveType::Sphere(_)));
+        let (start, end) = (PrimitiveType::Triangle(triangle), PrimitiveType::Sphere(sphere.end));
+        let mixed = PrimitiveType::moving(start, end);
+        assert!(matches!(mixed, PrimitiveType::Triangle(_)));
+    }
+}
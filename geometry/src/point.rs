@@ -111,6 +111,18 @@ impl core::ops::Add<Vector3d> for Point3d {
     }
 }
 
+impl core::ops::Sub<Vector3d> for Point3d {
+    type Output = Self;
+
+    fn sub(self, other: Vector3d) -> Self::Output {
+        Self::Output {
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
+        }
+    }
+}
+
 impl core::ops::Mul<f32> for Point3d {
     type Output = Self;
 
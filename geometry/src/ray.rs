@@ -1,9 +1,15 @@
 use crate::{Point3d, Vector3d};
 
+/// A ray only hits things that are within the [t_min; t_max] interval of distances from its
+/// origin, by default that is everything in front of the origin. Its time tells where moving
+/// primitives are when it passes, 0.0 being the start of the shutter interval and 1.0 the end.
 #[derive(Copy, Clone)]
 pub struct Ray3d {
     origin: Point3d,
     direction: Vector3d,
+    t_min: f32,
+    t_max: f32,
+    time: f32,
 }
 
 impl Ray3d {
@@ -11,10 +17,35 @@ impl Ray3d {
         Ray3d {
             origin: Point3d::new(),
             direction: Vector3d::new(),
+            t_min: 0.0,
+            t_max: f32::MAX,
+            time: 0.0,
         }
     }
     pub fn from(origin: Point3d, direction: Vector3d) -> Ray3d {
-        Ray3d { origin, direction }
+        Ray3d {
+            origin,
+            direction,
+            t_min: 0.0,
+            t_max: f32::MAX,
+            time: 0.0,
+        }
+    }
+
+    pub fn with_interval(mut self, t_min: f32, t_max: f32) -> Ray3d {
+        self.t_min = t_min;
+        self.t_max = t_max;
+        self
+    }
+
+    pub fn with_t_max(mut self, t_max: f32) -> Ray3d {
+        self.t_max = t_max;
+        self
+    }
+
+    pub fn with_time(mut self, time: f32) -> Ray3d {
+        self.time = time;
+        self
     }
 
     pub fn get_origin(&self) -> Point3d {
@@ -24,6 +55,26 @@ impl Ray3d {
     pub fn get_direction(&self) -> Vector3d {
         self.direction
     }
+
+    pub fn get_t_min(&self) -> f32 {
+        self.t_min
+    }
+
+    pub fn get_t_max(&self) -> f32 {
+        self.t_max
+    }
+
+    pub fn get_time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn get_point_at(&self, t: f32) -> Point3d {
+        self.origin + self.direction * t
+    }
+
+    pub fn in_interval(&self, t: f32) -> bool {
+        t >= self.t_min && t <= self.t_max
+    }
 }
 
 // impl core::ops::Add<Vector3d> for Vector3d {
@@ -76,7 +127,7 @@ impl core::ops::Mul<f32> for Ray3d {
     type Output = Point3d;
 
     fn mul(self, other: f32) -> Self::Output {
-        (self.origin + self.direction) * other
+        self.get_point_at(other)
     }
 }
 //
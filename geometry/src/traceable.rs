@@ -1,4 +1,6 @@
 use crate::aabb::Aabb;
+use crate::hit::HitRecord;
+use crate::motion::Motion;
 use crate::ray::Ray3d;
 use crate::sphere::Sphere;
 use crate::triangle::Triangle;
@@ -7,6 +9,8 @@ use std::mem;
 
 pub trait TraceablePrimitive {
     fn get_distance_to(&self, ray: &Ray3d) -> Option<f32>;
+    /// Same as get_distance_to() but also tells where and how the primitive is hit
+    fn intersect(&self, ray: &Ray3d) -> Option<HitRecord>;
     fn get_normal(&self, surface_pt: &Point3d) -> Vector3d;
     fn get_bounding_box(&self) -> Aabb;
     fn get_centroid(&self) -> Point3d;
@@ -17,22 +21,55 @@ pub trait TraceablePrimitive {
 pub enum PrimitiveType {
     Triangle(Triangle),
     Sphere(Sphere),
+    MovingTriangle(Motion<Triangle>),
+    MovingSphere(Motion<Sphere>),
     //Point3d(Point3d)
 }
 
+impl PrimitiveType {
+    /// Primitive moving from its state at the start of the shutter interval to the one at the end,
+    /// both being the same kind. Unchanged if it stays in place.
+    pub fn moving(start: PrimitiveType, end: PrimitiveType) -> PrimitiveType {
+        match (start, end) {
+            (PrimitiveType::Triangle(a), PrimitiveType::Triangle(b)) if a.v != b.v => {
+                PrimitiveType::MovingTriangle(Motion::new(a, b))
+            }
+            (PrimitiveType::Sphere(a), PrimitiveType::Sphere(b))
+                if a.center != b.center || a.radius != b.radius =>
+            {
+                PrimitiveType::MovingSphere(Motion::new(a, b))
+            }
+            _ => start,
+        }
+    }
+}
+
 impl TraceablePrimitive for PrimitiveType {
     fn get_distance_to(&self, ray: &Ray3d) -> Option<f32> {
         match self {
             PrimitiveType::Triangle(t) => t.get_distance_to(ray),
             PrimitiveType::Sphere(s) => s.get_distance_to(ray),
+            PrimitiveType::MovingTriangle(m) => m.get_distance_to(ray),
+            PrimitiveType::MovingSphere(m) => m.get_distance_to(ray),
             //PrimitiveType::Point3d(p) => p.get_distance_to(ray),
         }
     }
 
+    fn intersect(&self, ray: &Ray3d) -> Option<HitRecord> {
+        match self {
+            PrimitiveType::Triangle(t) => t.intersect(ray),
+            PrimitiveType::Sphere(s) => s.intersect(ray),
+            PrimitiveType::MovingTriangle(m) => m.intersect(ray),
+            PrimitiveType::MovingSphere(m) => m.intersect(ray),
+        }
+    }
+
     fn get_normal(&self, surface_pt: &Point3d) -> Vector3d {
         match self {
             PrimitiveType::Triangle(t) => t.get_normal(surface_pt),
             PrimitiveType::Sphere(s) => s.get_normal(surface_pt),
+            PrimitiveType::MovingTriangle(m) => m.get_normal(surface_pt),
+            PrimitiveType::MovingSphere(m) => m.get_normal(surface_pt),
         }
     }
 
@@ -40,6 +77,8 @@ impl TraceablePrimitive for PrimitiveType {
         match self {
             PrimitiveType::Triangle(t) => t.get_bounding_box(),
             PrimitiveType::Sphere(s) => s.get_bounding_box(),
+            PrimitiveType::MovingTriangle(m) => m.get_bounding_box(),
+            PrimitiveType::MovingSphere(m) => m.get_bounding_box(),
         }
     }
 
@@ -47,6 +86,8 @@ impl TraceablePrimitive for PrimitiveType {
         match self {
             PrimitiveType::Triangle(t) => t.get_centroid(),
             PrimitiveType::Sphere(s) => s.get_centroid(),
+            PrimitiveType::MovingTriangle(m) => m.get_centroid(),
+            PrimitiveType::MovingSphere(m) => m.get_centroid(),
         }
     }
 
@@ -54,6 +95,10 @@ impl TraceablePrimitive for PrimitiveType {
         match self {
             PrimitiveType::Triangle(t) => PrimitiveType::Triangle(t.model_to_world(model)),
             PrimitiveType::Sphere(s) => PrimitiveType::Sphere(s.model_to_world(model)),
+            PrimitiveType::MovingTriangle(m) => {
+                PrimitiveType::MovingTriangle(m.model_to_world(model))
+            }
+            PrimitiveType::MovingSphere(m) => PrimitiveType::MovingSphere(m.model_to_world(model)),
         }
     }
 }
@@ -101,14 +146,40 @@ impl TraceablePrimitive for Aabb {
             tmax = tzmax;
         }
 
-        if tmin >= 0.0 {
+        // Clip the part of the ray inside the box to the ray's interval, a ray starting inside
+        // the box is at t_min distance from it
+        if tmax < ray.get_t_min() || tmin > ray.get_t_max() {
+            None
+        } else if tmin >= ray.get_t_min() {
             Some(tmin)
         } else {
-            Some(tmax)
+            Some(ray.get_t_min())
         }
     }
 
-    //fn intersect (&self, ray r)
+    fn intersect(&self, ray: &Ray3d) -> Option<HitRecord> {
+        let dist = self.get_distance_to(ray)?;
+        let pt = ray.get_point_at(dist);
+
+        // The normal is along the axis where the hit point is the closest to one of the faces
+        let mut normal = Vector3d::new();
+        let mut nearest_face_dist = f32::MAX;
+        for axis in 0..3 {
+            for (face, sign) in [(self.min[axis], -1.0), (self.max[axis], 1.0)] {
+                let face_dist = (pt[axis] - face).abs();
+                if face_dist < nearest_face_dist {
+                    nearest_face_dist = face_dist;
+                    normal = Vector3d::new();
+                    match axis {
+                        0 => normal.x = sign,
+                        1 => normal.y = sign,
+                        _ => normal.z = sign,
+                    }
+                }
+            }
+        }
+        Some(HitRecord::new(ray, dist, normal, 0.0, 0.0))
+    }
 
     fn get_normal(&self, _: &Point3d) -> Vector3d {
         Vector3d::new() //TODO: how to find out normal to Aabb?
@@ -141,12 +212,16 @@ impl TraceablePrimitive for Point3d {
         // dot product tells us cos of angle between the ray and the new vector,
         // if the angle is zero then our point lays on the ray
         let cos = new.normalize() * ray.get_direction().normalize();
-        if cos > 0.99999 {
+        if cos > 0.99999 && ray.in_interval(new.len()) {
             Some(new.len())
         } else {
             None
         }
     }
+    fn intersect(&self, ray: &Ray3d) -> Option<HitRecord> {
+        let dist = self.get_distance_to(ray)?;
+        Some(HitRecord::new(ray, dist, -ray.get_direction().normalize(), 0.0, 0.0))
+    }
     fn get_normal(&self, surface_pt: &Point3d) -> Vector3d {
         Vector3d::new()
     }
@@ -175,15 +250,23 @@ impl TraceablePrimitive for Sphere {
         let thc = (self.radius * self.radius - d_squared).sqrt();
         let t0 = tca - thc;
         let t1 = tca + thc;
-        if t0 >= 0.0 {
+        if ray.in_interval(t0) {
             Some(t0)
-        } else if t0 < 0.0 && t1 >= 0.0 {
+        } else if ray.in_interval(t1) {
             Some(t1)
         } else {
             None
         }
     }
 
+    fn intersect(&self, ray: &Ray3d) -> Option<HitRecord> {
+        let dist = self.get_distance_to(ray)?;
+        let normal = self.get_normal(&ray.get_point_at(dist));
+        let u = 0.5 + normal.z.atan2(normal.x) / (2.0 * std::f32::consts::PI);
+        let v = 0.5 + normal.y.asin() / std::f32::consts::PI;
+        Some(HitRecord::new(ray, dist, normal, u, v))
+    }
+
     fn get_normal(&self, surface_pt: &Point3d) -> Vector3d {
         (*surface_pt - self.center).normalize()
     }
@@ -195,10 +278,11 @@ impl TraceablePrimitive for Sphere {
         self.center
     }
 
+    // Non-uniform scalings would make it an ellipsoid, the radius gets their average
     fn model_to_world(&self, model: &Mat4f) -> Self {
         Sphere::new(
             Point3d::from(model * Point4d::from(self.center)),
-            self.radius, //TODO: need to scale it too!
+            self.radius * model.get_uniform_scale(),
         )
     }
 }
@@ -212,6 +296,15 @@ impl TraceablePrimitive for Triangle {
         }
     }
 
+    fn intersect(&self, ray: &Ray3d) -> Option<HitRecord> {
+        let (t, u, v) = self.moller_trumbore(ray)?;
+        let hit = HitRecord::new(ray, t, self.normal, u, v);
+        Some(hit
+            .with_shading_normal(self.get_shading_normal(u, v))
+            .with_tex_coords(self.get_tex_coords(u, v))
+            .with_tangent(self.get_tangent(u, v)))
+    }
+
     fn get_normal(&self, _: &Point3d) -> Vector3d {
         // Vec3f::new(0.0, 0.0, 0.0)
         self.normal
@@ -241,10 +334,19 @@ impl TraceablePrimitive for Triangle {
     }
 
     fn model_to_world(&self, model: &Mat4f) -> Self {
-        Triangle::new(
+        let tri = Triangle::new(
             Point3d::from(model * Point4d::from(self.v[0])),
             Point3d::from(model * Point4d::from(self.v[1])),
             Point3d::from(model * Point4d::from(self.v[2])),
-        )
+        );
+        Triangle {
+            vertex_normals: self.vertex_normals.map(|n| n.map(|x| model.transform_normal(&x))),
+            tex_coords: self.tex_coords,
+            vertex_tangents: self.vertex_tangents.map(|t| {
+                let sign = if model.is_mirroring() { -1.0 } else { 1.0 };
+                t.map(|(x, s)| (model.transform_direction(&x), s * sign))
+            }),
+            ..tri
+        }
     }
 }
@@ -1,11 +1,20 @@
 use crate::aabb::Aabb;
+use crate::mikktspace;
 use crate::ray::Ray3d;
 use crate::{max_of_three_f32, min_of_three_f32, Mat4f, Point3d, Point4d, Vector3d};
+use std::collections::HashMap;
 
 #[derive(Copy, Clone)]
 pub struct Triangle {
     pub v: [Point3d; 3],
     pub(crate) normal: Vector3d,
+    /// Interpolated across the triangle for smooth shading, in the order of the vertices
+    pub vertex_normals: Option<[Vector3d; 3]>,
+    /// Texture coordinates of the vertices
+    pub tex_coords: Option<[[f32; 2]; 3]>,
+    /// Per-vertex tangents along the direction of growing u, with the sign of the bitangent:
+    /// bitangent = sign * normal x tangent, as in MikkTSpace
+    pub vertex_tangents: Option<[(Vector3d, f32); 3]>,
     //parent: &Object,
 }
 
@@ -17,9 +26,84 @@ impl Triangle {
         Triangle {
             v: [v0, v1, v2],
             normal,
+            vertex_normals: None,
+            tex_coords: None,
+            vertex_tangents: None,
         }
     }
 
+    pub fn with_vertex_normals(mut self, vertex_normals: [Vector3d; 3]) -> Self {
+        self.vertex_normals = Some(vertex_normals);
+        self
+    }
+
+    pub fn with_tex_coords(mut self, tex_coords: [[f32; 2]; 3]) -> Self {
+        self.tex_coords = Some(tex_coords);
+        self
+    }
+
+    /// Texture coordinates at the point given by the barycentric coordinates, the barycentric
+    /// coordinates themselves if the vertices have none
+    pub fn get_tex_coords(&self, u: f32, v: f32) -> [f32; 2] {
+        match self.tex_coords {
+            Some(t) => [
+                t[0][0] * (1.0 - u - v) + t[1][0] * u + t[2][0] * v,
+                t[0][1] * (1.0 - u - v) + t[1][1] * u + t[2][1] * v,
+            ],
+            None => [u, v],
+        }
+    }
+
+    pub fn with_vertex_tangents(mut self, vertex_tangents: [(Vector3d, f32); 3]) -> Self {
+        self.vertex_tangents = Some(vertex_tangents);
+        self
+    }
+
+    /// Tangent and bitangent sign at the point given by the barycentric coordinates. The sign is
+    /// interpolated as well, where the vertices disagree the larger barycentric weight wins.
+    pub fn get_tangent(&self, u: f32, v: f32) -> Option<(Vector3d, f32)> {
+        let t = self.vertex_tangents?;
+        let tangent = (t[0].0 * (1.0 - u - v) + t[1].0 * u + t[2].0 * v).normalize();
+        let sign = t[0].1 * (1.0 - u - v) + t[1].1 * u + t[2].1 * v;
+        Some((tangent, if sign < 0.0 { -1.0 } else { 1.0 }))
+    }
+
+    /// Texture coordinates per unit of length on the triangle, the square root of the ratio of
+    /// the texture and world space areas
+    pub fn get_tex_density(&self) -> Option<f32> {
+        let t = self.tex_coords?;
+        let tex_area = ((t[1][0] - t[0][0]) * (t[2][1] - t[0][1])
+            - (t[2][0] - t[0][0]) * (t[1][1] - t[0][1]))
+            .abs();
+        let world_area = (self.v[1] - self.v[0]).crossprod(&(self.v[2] - self.v[0])).len();
+        if world_area > 0.0 {
+            Some((tex_area / world_area).sqrt())
+        } else {
+            None
+        }
+    }
+
+    /// Face normal, the same for the whole triangle
+    pub fn get_face_normal(&self) -> Vector3d {
+        self.normal
+    }
+
+    /// Normal at the point given by the barycentric coordinates from moller_trumbore, interpolated
+    /// from the vertex normals if there are any
+    pub fn get_shading_normal(&self, u: f32, v: f32) -> Vector3d {
+        match self.vertex_normals {
+            Some(n) => (n[0] * (1.0 - u - v) + n[1] * u + n[2] * v).normalize(),
+            None => self.normal,
+        }
+    }
+
+    // Angle between the edges meeting at the vertex
+    fn get_angle_at(&self, vertex: usize) -> f32 {
+        let e1 = (self.v[(vertex + 1) % 3] - self.v[vertex]).normalize();
+        let e2 = (self.v[(vertex + 2) % 3] - self.v[vertex]).normalize();
+        (e1 * e2).clamp(-1.0, 1.0).acos()
+    }
+
     fn _get_uv(&self, ray: &Ray3d) -> Option<(f32, f32)> {
         if let Some((_, u, v)) = self.moller_trumbore(ray) {
             Some((u, v))
@@ -35,7 +119,8 @@ impl Triangle {
         let pvec = ray.get_direction().crossprod(&v0v2);
         let det = v0v1 * pvec;
 
-        if det < EPSILON {
+        // Both sides are hit, refracted rays need to get out of closed meshes
+        if det.abs() < EPSILON {
             return None;
         }
 
@@ -54,6 +139,334 @@ impl Triangle {
         }
 
         let t = v0v2 * qvec * inv_det;
+        if !ray.in_interval(t) {
+            return None;
+        }
         Some((t, u, v))
     }
 }
+
+/// Gives vertex normals to the triangles that have none: the face normals of all the triangles
+/// sharing the vertex position, weighted by their angles at the vertex. Faces meeting at more than
+/// crease_angle (degrees) are left out so that sharp edges stay sharp.
+pub fn generate_vertex_normals(triangles: &mut [Triangle], crease_angle: f32) {
+    let cos_crease = crease_angle.to_radians().cos();
+    let get_key = |p: &Point3d| [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+
+    // (triangle, vertex) pairs sharing the position
+    let mut corners: HashMap<[u32; 3], Vec<(usize, usize)>> = HashMap::new();
+    for (tri_idx, tri) in triangles.iter().enumerate() {
+        for vertex in 0..3 {
+            corners.entry(get_key(&tri.v[vertex])).or_default().push((tri_idx, vertex));
+        }
+    }
+
+    let normals: Vec<Option<[Vector3d; 3]>> = triangles
+        .iter()
+        .map(|tri| {
+            if tri.vertex_normals.is_some() || !tri.normal.x.is_finite() {
+                return None;
+            }
+            let mut vertex_normals = [tri.normal; 3];
+            for vertex in 0..3 {
+                let mut sum = Vector3d::new();
+                for &(other_idx, other_vertex) in &corners[&get_key(&tri.v[vertex])] {
+                    let other = &triangles[other_idx];
+                    if other.normal * tri.normal >= cos_crease {
+                        sum = sum + other.normal * other.get_angle_at(other_vertex);
+                    }
+                }
+                vertex_normals[vertex] = sum.normalize();
+            }
+            Some(vertex_normals)
+        })
+        .collect();
+
+    for (tri, n) in triangles.iter_mut().zip(normals) {
+        if n.is_some() {
+            tri.vertex_normals = n;
+        }
+    }
+}
+
+/// Gives vertex tangents to the triangles with texture coordinates, the same ones MikkTSpace
+/// does: normal maps baked by Blender, Substance and the like then shade without seams. Meant to
+/// be run after generate_vertex_normals, the tangents are made perpendicular to the vertex
+/// normals.
+pub fn generate_vertex_tangents(triangles: &mut [Triangle]) {
+    let tangents = mikktspace::generate_tangents(triangles);
+    for (tri, t) in triangles.iter_mut().zip(tangents) {
+        if t.is_some() {
+            tri.vertex_tangents = t;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn assert_tangent(actual: (Vector3d, f32), expected: (Vector3d, f32)) {
+        assert!((actual.0 - expected.0).len() < EPSILON, "{:?} != {:?}", actual, expected);
+        assert_eq!(actual.1, expected.1);
+    }
+
+    // Quad in the z = 0 plane facing +Z, split along its diagonal, both triangles wound
+    // counterclockwise. get_uv maps the positions to texture coordinates.
+    fn get_quad<F>(x0: f32, x1: f32, get_uv: F) -> [Triangle; 2]
+    where
+        F: Fn(Point3d) -> [f32; 2],
+    {
+        let p = [(x0, 0.0), (x1, 0.0), (x1, 1.0), (x0, 1.0)]
+            .map(|(x, y)| Point3d::from_coords(x, y, 0.0));
+        let tri = |a: usize, b: usize, c: usize| {
+            Triangle::new(p[a], p[b], p[c]).with_tex_coords([a, b, c].map(|i| get_uv(p[i])))
+        };
+        [tri(0, 1, 2), tri(0, 2, 3)]
+    }
+
+    #[test]
+    fn t_tangents_quad() {
+        let x_axis = Vector3d::from_coords(1.0, 0.0, 0.0);
+        let y_axis = Vector3d::from_coords(0.0, 1.0, 0.0);
+
+        let mut quad = get_quad(0.0, 1.0, |p| [p.x, p.y]);
+        generate_vertex_tangents(&mut quad);
+        for tri in &quad {
+            for t in tri.vertex_tangents.unwrap() {
+                assert_tangent(t, (x_axis, 1.0));
+            }
+        }
+
+        // u growing upwards and v to the left, still not mirrored
+        let mut quad = get_quad(0.0, 1.0, |p| [p.y, 1.0 - p.x]);
+        generate_vertex_tangents(&mut quad);
+        for tri in &quad {
+            for t in tri.vertex_tangents.unwrap() {
+                assert_tangent(t, (y_axis, 1.0));
+            }
+            // The bitangent points where v grows
+            let (tangent, sign) = tri.get_tangent(0.3, 0.3).unwrap();
+            assert!((tri.get_face_normal().crossprod(&tangent) * sign + x_axis).len() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn t_tangents_mirrored_seam() {
+        // The left half mirrors the texture of the right one, u = |x|. The vertices at x = 0 are
+        // shared by both halves with the same position, normal and texture coordinates.
+        let mut triangles =
+            [get_quad(-1.0, 0.0, |p| [-p.x, p.y]), get_quad(0.0, 1.0, |p| [p.x, p.y])].concat();
+        generate_vertex_normals(&mut triangles, 60.0);
+        generate_vertex_tangents(&mut triangles);
+
+        // Mirrored: the tangent follows u to the left and the bitangent still goes up
+        for tri in &triangles[..2] {
+            for t in tri.vertex_tangents.unwrap() {
+                assert_tangent(t, (Vector3d::from_coords(-1.0, 0.0, 0.0), -1.0));
+            }
+        }
+        // The seam does not average the two sides
+        for tri in &triangles[2..] {
+            for t in tri.vertex_tangents.unwrap() {
+                assert_tangent(t, (Vector3d::from_coords(1.0, 0.0, 0.0), 1.0));
+            }
+        }
+        for tri in &triangles {
+            let (tangent, sign) = tri.get_tangent(0.2, 0.5).unwrap();
+            let bitangent = tri.get_shading_normal(0.2, 0.5).crossprod(&tangent) * sign;
+            assert!((bitangent - Vector3d::from_coords(0.0, 1.0, 0.0)).len() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn t_tangents_shared_across_fold() {
+        // Two quads folded along x = 0 with a continuous texture mapping, the vertices along the
+        // fold get one tangent for both sides
+        let fold = |p: Point3d| Point3d::from_coords(p.x, p.y, -p.x.abs());
+        let quads = [
+            get_quad(-1.0, 0.0, |p| [p.x + 1.0, p.y]),
+            get_quad(0.0, 1.0, |p| [p.x + 1.0, p.y]),
+        ];
+        let mut triangles: Vec<Triangle> = quads
+            .concat()
+            .into_iter()
+            .map(|tri| {
+                Triangle::new(fold(tri.v[0]), fold(tri.v[1]), fold(tri.v[2]))
+                    .with_tex_coords(tri.tex_coords.unwrap())
+            })
+            .collect();
+        generate_vertex_normals(&mut triangles, 90.0);
+        generate_vertex_tangents(&mut triangles);
+
+        let mut on_fold = Vec::new();
+        for tri in &triangles {
+            let normals = tri.vertex_normals.unwrap();
+            for (i, &t) in tri.vertex_tangents.unwrap().iter().enumerate() {
+                assert!((t.0 * normals[i]).abs() < EPSILON);
+                assert_eq!(t.1, 1.0);
+                if tri.v[i].x == 0.0 {
+                    on_fold.push(t.0);
+                }
+            }
+        }
+        assert!(on_fold.len() > 2);
+        for t in &on_fold {
+            assert!((*t - Vector3d::from_coords(1.0, 0.0, 0.0)).len() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn t_tangent_mixed_handedness() {
+        let x_axis = Vector3d::from_coords(1.0, 0.0, 0.0);
+        let tri = Triangle::new(
+            Point3d::from_coords(0.0, 0.0, 0.0),
+            Point3d::from_coords(1.0, 0.0, 0.0),
+            Point3d::from_coords(0.0, 1.0, 0.0),
+        )
+        .with_vertex_tangents([(x_axis, 1.0), (x_axis, -1.0), (x_axis, -1.0)]);
+
+        assert_eq!(tri.get_tangent(0.0, 0.0).unwrap().1, 1.0);
+        assert_eq!(tri.get_tangent(0.1, 0.1).unwrap().1, 1.0);
+        assert_eq!(tri.get_tangent(0.6, 0.0).unwrap().1, -1.0);
+        assert_eq!(tri.get_tangent(0.0, 0.9).unwrap().1, -1.0);
+    }
+
+    fn assert_normal(actual: Vector3d, expected: Vector3d) {
+        assert!((actual - expected).len() < EPSILON, "{:?} != {:?}", actual, expected);
+    }
+
+    // Unit cube, two triangles per face, all wound counterclockwise seen from outside
+    fn get_cube() -> Vec<Triangle> {
+        let p = |i: usize| {
+            let bit = |b: usize| (i >> b & 1) as f32;
+            Point3d::from_coords(bit(0), bit(1), bit(2))
+        };
+        let faces =
+            [[0, 4, 6, 2], [1, 3, 7, 5], [0, 1, 5, 4], [2, 6, 7, 3], [0, 2, 3, 1], [4, 5, 7, 6]];
+        faces
+            .iter()
+            .flat_map(|f| {
+                vec![
+                    Triangle::new(p(f[0]), p(f[1]), p(f[2])),
+                    Triangle::new(p(f[0]), p(f[2]), p(f[3])),
+                ]
+            })
+            .collect()
+    }
+
+    // Strip of quads along an arc of the unit cylinder around Y facing outwards, step degrees apart
+    fn get_arc(num_quads: usize, step: f32) -> Vec<Triangle> {
+        let p = |i: usize, y: f32| {
+            let (sin, cos) = (i as f32 * step).to_radians().sin_cos();
+            Point3d::from_coords(sin, y, cos)
+        };
+        (0..num_quads)
+            .flat_map(|i| {
+                let (a, b, c, d) = (p(i, 0.0), p(i + 1, 0.0), p(i + 1, 1.0), p(i, 1.0));
+                vec![Triangle::new(a, b, c), Triangle::new(a, c, d)]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn t_vertex_normals_crease() {
+        let mut cube = get_cube();
+        for tri in &cube {
+            let center =
+                (tri.v[0] + tri.v[1] + tri.v[2]) / 3.0 - Point3d::from_coords(0.5, 0.5, 0.5);
+            assert!(tri.get_face_normal() * center > 0.0);
+        }
+        generate_vertex_normals(&mut cube, 30.0);
+        for tri in &cube {
+            for n in tri.vertex_normals.unwrap() {
+                assert_normal(n, tri.get_face_normal());
+            }
+        }
+
+        // Above 90 degrees the corners get the diagonal, each face weighs in with its right angle
+        let mut cube = get_cube();
+        generate_vertex_normals(&mut cube, 100.0);
+        for tri in &cube {
+            for (p, n) in tri.v.iter().zip(tri.vertex_normals.unwrap()) {
+                let diagonal = *p - Point3d::from_coords(0.5, 0.5, 0.5);
+                assert_normal(n, diagonal.normalize());
+            }
+        }
+    }
+
+    #[test]
+    fn t_vertex_normals_smooth() {
+        // A flat patch keeps the face normal everywhere
+        let mut patch =
+            [get_quad(0.0, 1.0, |p| [p.x, p.y]), get_quad(1.0, 2.0, |p| [p.x, p.y])].concat();
+        generate_vertex_normals(&mut patch, 30.0);
+        let z_axis = Vector3d::from_coords(0.0, 0.0, 1.0);
+        for tri in &patch {
+            for n in tri.vertex_normals.unwrap() {
+                assert_normal(n, z_axis);
+            }
+        }
+
+        // The inner vertices of a curved patch average their quads to the cylinder normal, the
+        // outer ones keep the face normal of their only quad
+        let mut arc = get_arc(4, 10.0);
+        generate_vertex_normals(&mut arc, 30.0);
+        for tri in &arc {
+            for (p, n) in tri.v.iter().zip(tri.vertex_normals.unwrap()) {
+                let angle = p.x.atan2(p.z).to_degrees();
+                if angle > 1.0 && angle < 39.0 {
+                    assert_normal(n, Vector3d::from_coords(p.x, 0.0, p.z));
+                } else {
+                    assert_normal(n, tri.get_face_normal());
+                }
+            }
+        }
+
+        // Past the crease angle the quads stay faceted
+        let mut arc = get_arc(4, 10.0);
+        generate_vertex_normals(&mut arc, 5.0);
+        for tri in &arc {
+            for n in tri.vertex_normals.unwrap() {
+                assert_normal(n, tri.get_face_normal());
+            }
+        }
+    }
+
+    #[test]
+    fn t_vertex_normals_kept() {
+        let up = Vector3d::from_coords(0.0, 1.0, 0.0);
+        let mut quad = get_quad(0.0, 1.0, |p| [p.x, p.y]);
+        quad[0] = quad[0].with_vertex_normals([up; 3]);
+        generate_vertex_normals(&mut quad, 30.0);
+        assert_eq!(quad[0].vertex_normals.unwrap().map(|n| n.y), [1.0; 3]);
+        assert!(quad[1].vertex_normals.is_some());
+    }
+
+    #[test]
+    fn t_shading_normal() {
+        let tri = Triangle::new(
+            Point3d::from_coords(0.0, 0.0, 0.0),
+            Point3d::from_coords(1.0, 0.0, 0.0),
+            Point3d::from_coords(0.0, 1.0, 0.0),
+        );
+        let z_axis = Vector3d::from_coords(0.0, 0.0, 1.0);
+        assert_normal(tri.get_shading_normal(0.3, 0.3), z_axis);
+
+        let n = [
+            Vector3d::from_coords(1.0, 0.0, 1.0).normalize(),
+            Vector3d::from_coords(-1.0, 0.0, 1.0).normalize(),
+            z_axis,
+        ];
+        let tri = tri.with_vertex_normals(n);
+        assert_normal(tri.get_shading_normal(0.0, 0.0), n[0]);
+        assert_normal(tri.get_shading_normal(1.0, 0.0), n[1]);
+        assert_normal(tri.get_shading_normal(0.0, 1.0), n[2]);
+        // Halfway between the first two the sideways parts cancel out
+        assert_normal(tri.get_shading_normal(0.5, 0.0), z_axis);
+        let n = tri.get_shading_normal(0.2, 0.3);
+        assert!((n.len() - 1.0).abs() < EPSILON && n.x > 0.0);
+    }
+}
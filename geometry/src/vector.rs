@@ -1,6 +1,6 @@
 use crate::Point3d;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Vector3d {
     pub x: f32,
     pub y: f32,
@@ -0,0 +1,251 @@
+use rayon::prelude::*;
+
+use geometry::aabb::Aabb;
+use geometry::{Point3d, TraceablePrimitive};
+
+use crate::{
+    get_morton_key, join_subtrees, OctreeItem, OctreeLeafNode, OctreeNode, OwnedOctree, ParOctree,
+    RadixSpan, PAR_OCTREE_SERIAL_THRESHOLD,
+};
+
+/// How the primitives of a node are divided between its children
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SplitMethod {
+    /// Split at the highest differing bit of the primitives' Morton keys, the way [`ParOctree`]
+    /// does. Fastest to build.
+    Morton,
+    /// Split where the Surface Area Heuristic is the lowest, the candidate positions are the
+    /// borders of `num_bins` equal bins along each axis
+    BinnedSah { num_bins: usize },
+    /// Top-down binned SAH splits until a node holds `max_morton_len` primitives or fewer, then
+    /// each such subtree is built from its Morton keys by [`ParOctree`]. The SAH levels are not
+    /// a refinement of a Morton tree built beforehand, the top of the tree is SAH only. Most of
+    /// the SAH cost is in the leaves, so the tree beats a Morton one only with a small
+    /// `max_morton_len`, a few times the leaf capacity.
+    Hybrid { num_bins: usize, max_morton_len: usize },
+}
+
+/// Configures how [`OwnedOctree`] gets built
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct OctreeBuilder {
+    split_method: SplitMethod,
+}
+
+// Per-primitive data needed by the SAH splits, indexed the same way as the primitives
+struct SahInput {
+    bounds: Vec<Aabb>,
+    centroids: Vec<Point3d>,
+}
+
+#[derive(Copy, Clone)]
+struct SahBin {
+    bb: Aabb,
+    num_items: usize,
+}
+
+impl OctreeBuilder {
+    pub fn new() -> Self {
+        OctreeBuilder {
+            split_method: SplitMethod::Morton,
+        }
+    }
+
+    pub fn split_method(mut self, split_method: SplitMethod) -> Self {
+        self.split_method = split_method;
+        self
+    }
+
+    pub fn build<P, const N: usize>(&self, primitives: &[P]) -> OwnedOctree<P, N>
+    where
+        P: TraceablePrimitive + Copy + Send + Sync,
+    {
+        if self.split_method == SplitMethod::Morton {
+            return OwnedOctree::from(ParOctree::<P, N>::new(primitives));
+        }
+
+        let input = SahInput {
+            bounds: primitives.par_iter().map(|x| x.get_bounding_box()).collect(),
+            centroids: primitives.par_iter().map(|x| x.get_centroid()).collect(),
+        };
+        let mut items = ParOctree::<P, N>::linearize_primitives(primitives);
+
+        let nodes = if items.is_empty() {
+            Vec::new()
+        } else {
+            self.build_sah::<P, N>(primitives, &input, &mut items)
+        };
+        OwnedOctree::pack(nodes, primitives)
+    }
+
+    fn build_sah<P, const N: usize>(
+        &self,
+        primitives: &[P],
+        input: &SahInput,
+        items: &mut [OctreeItem],
+    ) -> Vec<OctreeNode<OctreeLeafNode<N>>>
+    where
+        P: TraceablePrimitive + Copy + Send + Sync,
+    {
+        if items.len() <= N {
+            return vec![OctreeNode::Leaf(OctreeLeafNode::<N>::from_items(primitives, items))];
+        }
+
+        let num_bins = match self.split_method {
+            SplitMethod::Hybrid { max_morton_len, .. } if items.len() <= max_morton_len => {
+                return OctreeBuilder::build_morton::<P, N>(primitives, input, items);
+            }
+            SplitMethod::Hybrid { num_bins, .. } => num_bins,
+            SplitMethod::BinnedSah { num_bins } => num_bins,
+            SplitMethod::Morton => unreachable!(),
+        };
+
+        // Split up to three times so that the new inner node gets up to eight children, children
+        // that fit into a leaf are not split further
+        let mut children: Vec<&mut [OctreeItem]> = vec![items];
+        for _ in 0..3 {
+            children = children
+                .into_iter()
+                .flat_map(|child| {
+                    if child.len() > N {
+                        let split_at = OctreeBuilder::get_sah_split(input, child, num_bins);
+                        let (left, right) = child.split_at_mut(split_at);
+                        vec![left, right]
+                    } else {
+                        vec![child]
+                    }
+                })
+                .collect();
+        }
+
+        let subtrees: Vec<Vec<OctreeNode<OctreeLeafNode<N>>>> =
+            if children.iter().map(|x| x.len()).sum::<usize>() > PAR_OCTREE_SERIAL_THRESHOLD {
+                children
+                    .into_par_iter()
+                    .map(|child| self.build_sah::<P, N>(primitives, input, child))
+                    .collect()
+            } else {
+                children
+                    .into_iter()
+                    .map(|child| self.build_sah::<P, N>(primitives, input, child))
+                    .collect()
+            };
+
+        join_subtrees(subtrees)
+    }
+
+    fn build_morton<P, const N: usize>(
+        primitives: &[P],
+        input: &SahInput,
+        items: &mut [OctreeItem],
+    ) -> Vec<OctreeNode<OctreeLeafNode<N>>>
+    where
+        P: TraceablePrimitive + Copy + Send + Sync,
+    {
+        // Keys relative to the bounds of the subtree, not of the whole scene: the SAH splits do
+        // not follow the Morton grid, and the first Morton splits would cut off slivers of it
+        let bounds: Aabb = items.iter().map(|x| input.bounds[x.idx]).sum();
+        let (min, range) = (bounds.get_min(), bounds.get_max() - bounds.get_min());
+        for item in items.iter_mut() {
+            item.key = get_morton_key(input.centroids[item.idx], min, range);
+        }
+        items.par_sort_by_key(|x| x.key);
+        let radix_nodes = ParOctree::<P, N>::build_radix_tree(items);
+        let span = RadixSpan {
+            first: 0,
+            last: items.len() - 1,
+            node: Some(0),
+        };
+        ParOctree::<P, N>::build(primitives, items, &radix_nodes, span)
+    }
+
+    // Reorders the items so that the ones going to the left child come first, returns the number
+    // of them
+    fn get_sah_split(input: &SahInput, items: &mut [OctreeItem], num_bins: usize) -> usize {
+        let centroid_bb: Aabb = items
+            .iter()
+            .map(|x| {
+                let c = input.centroids[x.idx];
+                Aabb::from_points(c, c)
+            })
+            .sum();
+        let min = centroid_bb.get_min();
+        let max = centroid_bb.get_max();
+        let num_bins = num_bins.max(2);
+
+        let get_bin = |item: &OctreeItem, axis: usize| -> usize {
+            let offset = (input.centroids[item.idx][axis] - min[axis]) / (max[axis] - min[axis]);
+            ((offset * num_bins as f32) as usize).min(num_bins - 1)
+        };
+
+        // (cost, axis, last bin going to the left child)
+        let mut best: Option<(f32, usize, usize)> = None;
+        for axis in 0..3 {
+            if max[axis] <= min[axis] {
+                continue;
+            }
+
+            let mut bins = vec![
+                SahBin {
+                    bb: Aabb::new(),
+                    num_items: 0
+                };
+                num_bins
+            ];
+            for item in items.iter() {
+                let bin = &mut bins[get_bin(item, axis)];
+                bin.bb += input.bounds[item.idx];
+                bin.num_items += 1;
+            }
+
+            // Sweep from the right to get the costs of the right sides of all the candidate
+            // splits, then from the left to get the full costs
+            let mut right_costs = vec![0.0_f32; num_bins];
+            let mut right = SahBin {
+                bb: Aabb::new(),
+                num_items: 0,
+            };
+            for i in (1..num_bins).rev() {
+                right.bb += bins[i].bb;
+                right.num_items += bins[i].num_items;
+                right_costs[i - 1] = right.bb.get_surface_area() * right.num_items as f32;
+            }
+
+            let mut left = SahBin {
+                bb: Aabb::new(),
+                num_items: 0,
+            };
+            for i in 0..num_bins - 1 {
+                left.bb += bins[i].bb;
+                left.num_items += bins[i].num_items;
+                if left.num_items == 0 || left.num_items == items.len() {
+                    continue;
+                }
+                let cost = left.bb.get_surface_area() * left.num_items as f32 + right_costs[i];
+                if best.is_none() || cost < best.unwrap().0 {
+                    best = Some((cost, axis, i));
+                }
+            }
+        }
+
+        match best {
+            // All the centroids are at the same place, any split is as good as another one
+            None => items.len() / 2,
+            Some((_, axis, last_left_bin)) => {
+                let mut split_at = 0;
+                for i in 0..items.len() {
+                    if get_bin(&items[i], axis) <= last_left_bin {
+                        items.swap(i, split_at);
+                        split_at += 1;
+                    }
+                }
+                split_at
+            }
+        }
+    }
+}
+
+impl Default for OctreeBuilder {
+    fn default() -> Self {
+        OctreeBuilder::new()
+    }
+}
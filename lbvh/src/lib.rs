@@ -5,7 +5,7 @@ use rayon::prelude::*;
 use geometry::aabb::Aabb;
 use geometry::ray::Ray3d;
 use geometry::triangle::Triangle;
-use geometry::{Point3d, TraceablePrimitive, Vector3d};
+use geometry::{HitRecord, Point3d, TraceablePrimitive, Vector3d};
 use morton_encoding::morton_encode;
 use std::cmp::Ordering;
 use std::fmt::Formatter;
@@ -13,8 +13,27 @@ use std::marker;
 use std::marker::PhantomData;
 use std::ops::{AddAssign, Deref};
 
+pub use crate::builder::{OctreeBuilder, SplitMethod};
+
+mod builder;
+
 const OCTREE_MAX_NUM_CHILDREN: usize = 8;
 
+// Relative costs of visiting an inner node and of intersecting a primitive, used by the Surface
+// Area Heuristic
+const SAH_TRAVERSAL_COST: f32 = 1.0;
+const SAH_INTERSECTION_COST: f32 = 1.0;
+
+// Sub-trees spanning this many primitives or fewer are built by a single thread
+const PAR_OCTREE_SERIAL_THRESHOLD: usize = 4096;
+
+fn get_morton_key(centroid: Point3d, min: Point3d, range: Vector3d) -> u64 {
+    let positive = centroid - min;
+    let x: u16 = (positive.x * u16::MAX as f32 / range.x) as u16;
+    let y: u16 = (positive.y * u16::MAX as f32 / range.y) as u16;
+    let z: u16 = (positive.z * u16::MAX as f32 / range.z) as u16;
+    morton_encode([x, y, z])
+}
 
 #[derive(Debug)]
 struct OctreeItem {
@@ -42,22 +61,36 @@ struct OctreeInnerNode {
     bb: Aabb,
     children_idx: [Option<usize>; OCTREE_MAX_NUM_CHILDREN],
 }
+// Leaf nodes either refer to the primitives by their indices or own a contiguous range of them
 #[derive(Copy, Clone, PartialEq, Debug)]
-enum OctreeNode<const N: usize> {
-    Leaf(OctreeLeafNode<N>),
+struct PackedLeafNode {
+    bb: Aabb,
+    first: usize,
+    len: usize,
+}
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum OctreeNode<L> {
+    Leaf(L),
     Inner(OctreeInnerNode),
 }
-impl<const N: usize> OctreeNode<N> {
+
+trait LeafNode: Copy + std::fmt::Display {
+    fn get_bb(&self) -> Aabb;
+    fn set_bb(&mut self, bb: Aabb);
+    fn get_num_items(&self) -> usize;
+}
+
+impl<L: LeafNode> OctreeNode<L> {
     fn get_bb(&self) -> Aabb {
         match self {
             OctreeNode::Inner(n) => n.bb,
-            OctreeNode::Leaf(n) => n.bb,
+            OctreeNode::Leaf(n) => n.get_bb(),
         }
     }
     fn set_bb(&mut self, bb: Aabb) {
         match self {
             OctreeNode::Inner(n) => n.bb = bb,
-            OctreeNode::Leaf(n) => n.bb = bb,
+            OctreeNode::Leaf(n) => n.set_bb(bb),
         }
     }
 
@@ -67,6 +100,17 @@ impl<const N: usize> OctreeNode<N> {
             OctreeNode::Leaf(n) => panic!("Leaf node shall not have children!"),
         }
     }
+
+    // Used when a sub-tree built on its own gets appended to the end of another tree's node list
+    fn offset_children(mut self, offset: usize) -> Self {
+        if let OctreeNode::Inner(n) = &mut self {
+            n.children_idx
+                .iter_mut()
+                .filter_map(|x| x.as_mut())
+                .for_each(|child_idx| *child_idx += offset);
+        }
+        self
+    }
 }
 impl<const N: usize> OctreeLeafNode<N> {
     fn new() -> OctreeLeafNode<N> {
@@ -75,6 +119,54 @@ impl<const N: usize> OctreeLeafNode<N> {
             items_idx: [None; N],
         }
     }
+
+    fn from_items<P: TraceablePrimitive>(primitives: &[P], elems: &[OctreeItem]) -> OctreeLeafNode<N> {
+        let mut leaf = OctreeLeafNode::<N>::new();
+        elems.iter().enumerate().for_each(|(idx, item)| {
+            leaf.items_idx[idx] = Some(item.idx);
+            leaf.bb += primitives[item.idx].get_bounding_box();
+        });
+        leaf
+    }
+}
+impl<const N: usize> LeafNode for OctreeLeafNode<N> {
+    fn get_bb(&self) -> Aabb {
+        self.bb
+    }
+    fn set_bb(&mut self, bb: Aabb) {
+        self.bb = bb;
+    }
+    fn get_num_items(&self) -> usize {
+        self.items_idx.iter().filter(|x| x.is_some()).count()
+    }
+}
+impl<const N: usize> std::fmt::Display for OctreeLeafNode<N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, items:[", self.bb)?;
+        for i in self.items_idx {
+            match i {
+                None => write!(f, "None,")?,
+                Some(item) => write!(f, "{},", item)?,
+            };
+        }
+        write!(f, "]")
+    }
+}
+impl LeafNode for PackedLeafNode {
+    fn get_bb(&self) -> Aabb {
+        self.bb
+    }
+    fn set_bb(&mut self, bb: Aabb) {
+        self.bb = bb;
+    }
+    fn get_num_items(&self) -> usize {
+        self.len
+    }
+}
+impl std::fmt::Display for PackedLeafNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, items:[{}..{}]", self.bb, self.first, self.first + self.len)
+    }
 }
 impl OctreeInnerNode {
     fn new() -> OctreeInnerNode {
@@ -86,7 +178,7 @@ impl OctreeInnerNode {
 }
 
 pub struct Octree<'a, P, const N: usize> {
-    nodes: Vec<OctreeNode<N>>,
+    nodes: Vec<OctreeNode<OctreeLeafNode<N>>>,
     max_LEAF_CAPACTITY: usize,
     //_primitive: marker::PhantomData<P>,
     primitives: &'a [P],
@@ -107,13 +199,9 @@ where
         primitives
             .iter()
             .enumerate()
-            .map(|(idx, prim)| {
-                let positive = prim.get_centroid() - min;
-                let x: u16 = (positive.x * u16::MAX as f32 / range.x) as u16;
-                let y: u16 = (positive.y * u16::MAX as f32 / range.y) as u16;
-                let z: u16 = (positive.z * u16::MAX as f32 / range.z) as u16;
-                let key: u64 = morton_encode([x, y, z]);
-                OctreeItem { idx, key }
+            .map(|(idx, prim)| OctreeItem {
+                idx,
+                key: get_morton_key(prim.get_centroid(), min, range),
             })
             .collect()
     }
@@ -250,11 +338,7 @@ where
 
     // returns the index of the created leaf node
     fn add_leaf(&mut self, primitives: &[P], elems: &[OctreeItem]) -> usize {
-        let mut leaf = OctreeLeafNode::<N>::new();
-        elems.iter().enumerate().for_each(|(idx, item)| {
-            leaf.items_idx[idx] = Some(item.idx);
-            leaf.bb += primitives[item.idx].get_bounding_box();
-        });
+        let leaf = OctreeLeafNode::<N>::from_items(primitives, elems);
         self.nodes.push(OctreeNode::Leaf(leaf));
         self.nodes.len() - 1
     }
@@ -265,139 +349,591 @@ where
         self.nodes.len() - 1
     }
 
-    fn get_nearest_from_leaf(&self, leaf: &OctreeLeafNode<N>, ray: &Ray3d) -> Option<(usize, f32)> {
-        let mut nearest: Option<(usize, f32)> = None;
-        for i in 0..N {
-            match leaf.items_idx[i] {
-                None => break,
-                Some(item) => {
-                    let current = self.primitives[item].get_distance_to(ray);
+    pub fn traverse(&self, ray: &Ray3d) -> Option<HitRecord> {
+        traverse(&self.nodes, ray, |leaf, ray| get_nearest_from_leaf(self.primitives, leaf, ray))
+    }
 
-                    if current == None {
-                        continue;
-                    }
-                    let current = current.unwrap();
+    /// Tells whether any primitive is hit by the ray within its interval cut at t_max, e.g.
+    /// whether a shadow ray is blocked on its way to the light
+    pub fn occluded(&self, ray: &Ray3d, t_max: f32) -> bool {
+        is_occluded(&self.nodes, ray, t_max, |leaf, ray| {
+            is_occluded_in_leaf(self.primitives, leaf, ray)
+        })
+    }
 
-                    if nearest == None {
-                        nearest = Some((item, current))
-                    } else if current < nearest.unwrap().1 {
-                        nearest = Some((item, current))
-                    }
+    pub fn get_sah_cost(&self) -> f32 {
+        get_sah_cost(&self.nodes)
+    }
+}
+
+// The primitives are intersected in full right away, so the nearest hit comes out complete and
+// nothing needs to be intersected again once the traversal is over
+fn get_nearest_from_leaf<P, const N: usize>(
+    primitives: &[P],
+    leaf: &OctreeLeafNode<N>,
+    ray: &Ray3d,
+) -> Option<HitRecord>
+where
+    P: TraceablePrimitive,
+{
+    let mut ray = *ray;
+    let mut nearest: Option<HitRecord> = None;
+    for i in 0..N {
+        match leaf.items_idx[i] {
+            None => break,
+            Some(item) => {
+                // Anything found beyond the current ray's interval is farther than the nearest
+                // hit so far, so there is no need to compare the distances
+                if let Some(hit) = primitives[item].intersect(&ray) {
+                    ray = ray.with_t_max(hit.distance);
+                    nearest = Some(hit.with_primitive_id(item));
                 }
             }
         }
-        nearest
     }
+    nearest
+}
 
-    pub fn traverse(&self, ray: &Ray3d) -> Option<(usize, f32)> {
-        let mut nearest_overall: Option<(usize, f32)> = None;
+fn traverse<L, F>(nodes: &[OctreeNode<L>], ray: &Ray3d, get_nearest_from_leaf: F) -> Option<HitRecord>
+where
+    L: LeafNode,
+    F: Fn(&L, &Ray3d) -> Option<HitRecord>,
+{
+    let mut nearest_overall: Option<HitRecord> = None;
 
-        let mut node_stack: Vec<usize> = Vec::new();
-        node_stack.push(0);
-        while node_stack.len() > 0 {
-            let node_idx = node_stack.pop();
-            if node_idx == None {
-                break;
+    if nodes.is_empty() {
+        return nearest_overall;
+    }
+
+    // The ray's interval shrinks every time a nearer hit is found
+    let mut ray = *ray;
+
+    let mut node_stack: Vec<usize> = Vec::new();
+    node_stack.push(0);
+    while node_stack.len() > 0 {
+        let node_idx = node_stack.pop();
+        if node_idx == None {
+            break;
+        }
+        let current_node = &nodes[node_idx.unwrap()];
+        //let distance_to_bb = current_node.get_bb().get_distance_to(ray_origin, ray_dir);
+
+        match current_node {
+            OctreeNode::Leaf(leaf) => {
+                let nearest_in_this_leaf = get_nearest_from_leaf(leaf, &ray);
+                if let Some(hit) = nearest_in_this_leaf {
+                    nearest_overall = nearest_in_this_leaf;
+                    ray = ray.with_t_max(hit.distance);
+                }
             }
-            let current_node = &self.nodes[node_idx.unwrap()];
-            //let distance_to_bb = current_node.get_bb().get_distance_to(ray_origin, ray_dir);
+            OctreeNode::Inner(inner) => {
+                // we're not in a leaf:
+                // 1. get distance to all the children's bounding boxes
+                // 2. ideally we could sort them by the distance (nearest goes first), but this
+                //    made performance worse
+                // 3. push those children whose bounding boxes are hit within the ray's interval,
+                //    which ends at the nearest primitive known so far
+                inner
+                    .children_idx
+                    .iter()
+                    .filter_map(|&x| x)
+                    .filter(|&child_idx| nodes[child_idx].get_bb().get_distance_to(&ray) != None)
+                    .for_each(|child_idx| node_stack.push(child_idx));
+            }
+        }
+    }
+    nearest_overall
+}
 
-            match current_node {
-                OctreeNode::Leaf(leaf) => {
-                    let nearest_in_this_leaf = self.get_nearest_from_leaf(leaf, ray);
-                    if nearest_in_this_leaf != None {
-                        if nearest_overall != None {
-                            // safe to unwrap here
-                            if nearest_in_this_leaf.unwrap().1 < nearest_overall.unwrap().1 {
-                                nearest_overall = nearest_in_this_leaf;
-                            }
-                        } else {
-                            nearest_overall = nearest_in_this_leaf;
-                        }
-                    }
+fn is_occluded_in_leaf<P, const N: usize>(primitives: &[P], leaf: &OctreeLeafNode<N>, ray: &Ray3d) -> bool
+where
+    P: TraceablePrimitive,
+{
+    leaf.items_idx
+        .iter()
+        .filter_map(|&x| x)
+        .any(|item| primitives[item].get_distance_to(ray) != None)
+}
+
+// Unlike traverse() there is no need to find the nearest hit, so the search stops at the first
+// primitive hit within the ray's interval, which ends at t_max
+fn is_occluded<L, F>(nodes: &[OctreeNode<L>], ray: &Ray3d, t_max: f32, is_occluded_in_leaf: F) -> bool
+where
+    L: LeafNode,
+    F: Fn(&L, &Ray3d) -> bool,
+{
+    if nodes.is_empty() {
+        return false;
+    }
+
+    let ray = ray.with_t_max(t_max.min(ray.get_t_max()));
+
+    let mut node_stack: Vec<usize> = Vec::new();
+    node_stack.push(0);
+    while let Some(node_idx) = node_stack.pop() {
+        match &nodes[node_idx] {
+            OctreeNode::Leaf(leaf) => {
+                if is_occluded_in_leaf(leaf, &ray) {
+                    return true;
                 }
-                OctreeNode::Inner(inner) => {
-                    // we're not in a leaf:
-                    // 1. get distance to all the children's bounding boxes
-                    // 2. ideally we could sort them by the distance (nearest goes first), but this
-                    //    made performance worse
-                    // 3. if a distance to any primitive is not known yet:
-                    //    - push all children to the queue
-                    //    - else push those children whose distance is smaller than already known distance
-                    inner
-                        .children_idx
-                        .iter()
-                        .filter_map(|&x| x)
-                        .filter_map(|child_idx| {
-                            let child_node = self.nodes[child_idx];
-                            let dist: Option<f32> = child_node.get_bb().get_distance_to(ray);
-                            match dist {
-                                Some(d) => Some ((child_idx, dist.unwrap())),
-                                None => None,
-                            }
-                        })
-                        .filter(|(idx, dist)| {
-                            (nearest_overall == None)
-                                || (nearest_overall != None
-                                    && (*dist < nearest_overall.unwrap().1))
-                        })
-                        .for_each(|(idx, _)| node_stack.push(idx));
+            }
+            OctreeNode::Inner(inner) => {
+                inner
+                    .children_idx
+                    .iter()
+                    .filter_map(|&x| x)
+                    .filter(|&child_idx| nodes[child_idx].get_bb().get_distance_to(&ray) != None)
+                    .for_each(|child_idx| node_stack.push(child_idx));
+            }
+        }
+    }
+    false
+}
+
+// Makes an inner node out of sub-trees that were built on their own: the sub-trees' node lists are
+// appended to the new node one after another
+fn join_subtrees<L: LeafNode>(subtrees: Vec<Vec<OctreeNode<L>>>) -> Vec<OctreeNode<L>> {
+    let mut nodes: Vec<OctreeNode<L>> = Vec::with_capacity(1 + subtrees.iter().map(|x| x.len()).sum::<usize>());
+    nodes.push(OctreeNode::Inner(OctreeInnerNode::new()));
+    let mut inner_bb = Aabb::new();
+    for (i, subtree) in subtrees.into_iter().enumerate() {
+        let child_idx = nodes.len();
+        nodes[0].set_child(i, child_idx);
+        inner_bb += subtree[0].get_bb();
+        nodes.extend(subtree.into_iter().map(|node| node.offset_children(child_idx)));
+    }
+    nodes[0].set_bb(inner_bb);
+    nodes
+}
+
+// Expected cost of tracing a random ray through the tree according to the Surface Area Heuristic,
+// relative to the cost of intersecting one primitive
+fn get_sah_cost<L: LeafNode>(nodes: &[OctreeNode<L>]) -> f32 {
+    if nodes.is_empty() {
+        return 0.0;
+    }
+    let cost: f32 = nodes
+        .iter()
+        .map(|node| match node {
+            OctreeNode::Inner(inner) => SAH_TRAVERSAL_COST * inner.bb.get_surface_area(),
+            OctreeNode::Leaf(leaf) => {
+                SAH_INTERSECTION_COST * leaf.get_num_items() as f32 * leaf.get_bb().get_surface_area()
+            }
+        })
+        .sum();
+    let root_area = nodes[0].get_bb().get_surface_area();
+    if root_area > 0.0 {
+        cost / root_area
+    } else {
+        cost
+    }
+}
+
+fn fmt_nodes<L: LeafNode>(nodes: &[OctreeNode<L>], f: &mut Formatter<'_>) -> std::fmt::Result {
+    let mut node_stack: Vec<(usize, usize)> = Vec::new();
+    node_stack.push((0, 0));
+
+    while node_stack.len() > 0 {
+        let (node_idx, depth) = node_stack.pop().unwrap();
+
+        if depth == 0 {
+            write!(f, "<{}>:", node_idx);
+        } else if depth == 1 {
+            write!(f, "|-<{}>:", node_idx);
+        } else {
+            for i in 0..depth - 1 {
+                write!(f, "|  ");
+            }
+            write!(f, "|-<{}>:", node_idx);
+        }
+
+        match &nodes[node_idx] {
+            OctreeNode::Inner(inner) => {
+                for i in inner.children_idx {
+                    if i != None {
+                        node_stack.push((i.unwrap(), depth + 1));
+                    }
                 }
+                writeln!(f, " {}", inner.bb);
+            }
+            OctreeNode::Leaf(leaf) => {
+                writeln!(f, " {}", leaf);
             }
         }
-        nearest_overall
     }
+    write!(f, "Done!")
 }
 
 impl<'a, P, const N: usize> std::fmt::Display for Octree<'a, P, N> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let mut node_stack: Vec<(usize, usize)> = Vec::new();
-        node_stack.push((0, 0));
+        fmt_nodes(&self.nodes, f)
+    }
+}
 
-        while node_stack.len() > 0 {
-            let (node_idx, depth) = node_stack.pop().unwrap();
+// Internal node of the binary radix tree: covers the sorted items [first; last], the left child
+// covers [first; split] and the right one covers [split + 1; last]
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct RadixNode {
+    first: usize,
+    last: usize,
+    split: usize,
+}
 
-            if depth == 0 {
-                write!(f, "<{}>:", node_idx);
-            } else if depth == 1 {
-                write!(f, "|-<{}>:", node_idx);
-            } else {
-                for i in 0..depth - 1 {
-                    write!(f, "|  ");
-                }
-                write!(f, "|-<{}>:", node_idx);
+// A range of sorted items along with the radix tree internal node covering it, single items have
+// no internal node
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct RadixSpan {
+    first: usize,
+    last: usize,
+    node: Option<usize>,
+}
+
+impl RadixSpan {
+    fn len(&self) -> usize {
+        self.last - self.first + 1
+    }
+}
+
+/// Parallel counterpart of [`Octree`]: the binary radix tree over the sorted Morton keys is built
+/// in parallel as described in "Maximizing Parallelism in the Construction of BVHs, Octrees, and
+/// k-d Trees" by T. Karras, then every three levels of it are collapsed into one node with up to
+/// eight children.
+pub struct ParOctree<'a, P, const N: usize> {
+    nodes: Vec<OctreeNode<OctreeLeafNode<N>>>,
+    primitives: &'a [P],
+}
+
+impl<'a, P, const N: usize> ParOctree<'a, P, N>
+where
+    P: TraceablePrimitive + Copy + Send + Sync,
+{
+    fn linearize_primitives(primitives: &'a [P]) -> Vec<OctreeItem> {
+        let top_bb = primitives
+            .par_iter()
+            .map(|x| x.get_bounding_box())
+            .reduce(Aabb::new, |a, b| a + b);
+        let min: Point3d = top_bb.get_min();
+        let max: Point3d = top_bb.get_max();
+        let range = max - min;
+
+        primitives
+            .par_iter()
+            .enumerate()
+            .map(|(idx, prim)| OctreeItem {
+                idx,
+                key: get_morton_key(prim.get_centroid(), min, range),
+            })
+            .collect()
+    }
+
+    fn sort_primitives(primitives: &'a [P]) -> Vec<OctreeItem> {
+        let mut indexed_keys = ParOctree::<'a, P, N>::linearize_primitives(primitives);
+        indexed_keys.par_sort_by_key(|p| p.key);
+        indexed_keys
+    }
+
+    pub fn new(primitives: &'a [P]) -> ParOctree<'a, P, N> {
+        let indexed_keys = ParOctree::<'a, P, N>::sort_primitives(primitives);
+        let radix_nodes = ParOctree::<'a, P, N>::build_radix_tree(&indexed_keys);
+
+        let nodes = if indexed_keys.is_empty() {
+            Vec::new()
+        } else {
+            let root = RadixSpan {
+                first: 0,
+                last: indexed_keys.len() - 1,
+                node: if indexed_keys.len() > 1 { Some(0) } else { None },
+            };
+            ParOctree::<'a, P, N>::build(primitives, &indexed_keys, &radix_nodes, root)
+        };
+
+        ParOctree { nodes, primitives }
+    }
+
+    // Length of the common prefix of the keys at positions i and j, -1 if j is out of bounds.
+    // Equal keys are told apart by their positions, so that every key is unique.
+    fn get_common_prefix_len(elems: &[OctreeItem], i: usize, j: isize) -> i32 {
+        if j < 0 || j >= elems.len() as isize {
+            return -1;
+        }
+        let j = j as usize;
+        if elems[i].key == elems[j].key {
+            64 + (i as u64 ^ j as u64).leading_zeros() as i32
+        } else {
+            (elems[i].key ^ elems[j].key).leading_zeros() as i32
+        }
+    }
+
+    // Every internal node of the radix tree is found independently from the others, see Figure 4
+    // in the paper by Karras
+    fn get_radix_node(elems: &[OctreeItem], i: usize) -> RadixNode {
+        let delta = |j: isize| ParOctree::<'a, P, N>::get_common_prefix_len(elems, i, j);
+        let i = i as isize;
+
+        // Direction of the range covered by the node
+        let d: isize = if delta(i + 1) > delta(i - 1) { 1 } else { -1 };
+
+        // Upper bound for the length of the range
+        let delta_min = delta(i - d);
+        let mut l_max: isize = 2;
+        while delta(i + l_max * d) > delta_min {
+            l_max *= 2;
+        }
+
+        // The other end of the range, found with binary search
+        let mut l: isize = 0;
+        let mut t = l_max / 2;
+        while t >= 1 {
+            if delta(i + (l + t) * d) > delta_min {
+                l += t;
             }
+            t /= 2;
+        }
+        let j = i + l * d;
 
-            match &self.nodes[node_idx] {
-                OctreeNode::Inner(inner) => {
-                    for i in inner.children_idx {
-                        if i != None {
-                            node_stack.push((i.unwrap(), depth + 1));
-                        }
+        // The split position, found with binary search as well
+        let delta_node = delta(j);
+        let mut s: isize = 0;
+        let mut t = l;
+        loop {
+            t = (t + 1) / 2;
+            if delta(i + (s + t) * d) > delta_node {
+                s += t;
+            }
+            if t <= 1 {
+                break;
+            }
+        }
+
+        RadixNode {
+            first: i.min(j) as usize,
+            last: i.max(j) as usize,
+            split: (i + s * d + d.min(0)) as usize,
+        }
+    }
+
+    fn build_radix_tree(elems: &[OctreeItem]) -> Vec<RadixNode> {
+        if elems.len() < 2 {
+            return Vec::new();
+        }
+        (0..elems.len() - 1)
+            .into_par_iter()
+            .map(|i| ParOctree::<'a, P, N>::get_radix_node(elems, i))
+            .collect()
+    }
+
+    // Descends up to three levels down the radix tree to find at most eight children for the
+    // octree node covering the span. Spans that fit into a leaf are not split further.
+    fn get_children(radix_nodes: &[RadixNode], span: RadixSpan) -> Vec<RadixSpan> {
+        let mut children = vec![span];
+        for _ in 0..3 {
+            children = children
+                .into_iter()
+                .flat_map(|child| match child.node {
+                    Some(node_idx) if child.len() > N => {
+                        let node = radix_nodes[node_idx];
+                        vec![
+                            RadixSpan {
+                                first: node.first,
+                                last: node.split,
+                                node: if node.first < node.split { Some(node.split) } else { None },
+                            },
+                            RadixSpan {
+                                first: node.split + 1,
+                                last: node.last,
+                                node: if node.split + 1 < node.last { Some(node.split + 1) } else { None },
+                            },
+                        ]
                     }
-                    writeln!(f, " {}", inner.bb);
-                }
+                    _ => vec![child],
+                })
+                .collect();
+        }
+        children
+    }
+
+    // Big sub-trees are built in parallel, each of them into its own node list which is then
+    // appended to the parent's one
+    fn build(
+        primitives: &[P],
+        elems: &[OctreeItem],
+        radix_nodes: &[RadixNode],
+        span: RadixSpan,
+    ) -> Vec<OctreeNode<OctreeLeafNode<N>>> {
+        // Spans of up to N items become leaves, which only build_serial makes, get_children
+        // needs an inner node to split
+        if span.len() <= PAR_OCTREE_SERIAL_THRESHOLD.max(N) {
+            let mut nodes: Vec<OctreeNode<OctreeLeafNode<N>>> = Vec::new();
+            ParOctree::<'a, P, N>::build_serial(primitives, elems, radix_nodes, span, &mut nodes);
+            return nodes;
+        }
+
+        let subtrees: Vec<Vec<OctreeNode<OctreeLeafNode<N>>>> = ParOctree::<'a, P, N>::get_children(radix_nodes, span)
+            .into_par_iter()
+            .map(|child| ParOctree::<'a, P, N>::build(primitives, elems, radix_nodes, child))
+            .collect();
+
+        join_subtrees(subtrees)
+    }
+
+    // returns the index of the created node
+    fn build_serial(
+        primitives: &[P],
+        elems: &[OctreeItem],
+        radix_nodes: &[RadixNode],
+        span: RadixSpan,
+        nodes: &mut Vec<OctreeNode<OctreeLeafNode<N>>>,
+    ) -> usize {
+        if span.len() <= N {
+            let leaf = OctreeLeafNode::<N>::from_items(primitives, &elems[span.first..=span.last]);
+            nodes.push(OctreeNode::Leaf(leaf));
+            return nodes.len() - 1;
+        }
+
+        let inner_idx = nodes.len();
+        nodes.push(OctreeNode::Inner(OctreeInnerNode::new()));
+        let mut inner_bb = Aabb::new();
+        for (i, child) in ParOctree::<'a, P, N>::get_children(radix_nodes, span)
+            .into_iter()
+            .enumerate()
+        {
+            let child_idx = ParOctree::<'a, P, N>::build_serial(primitives, elems, radix_nodes, child, nodes);
+            nodes[inner_idx].set_child(i, child_idx);
+            inner_bb += nodes[child_idx].get_bb();
+        }
+        nodes[inner_idx].set_bb(inner_bb);
+        inner_idx
+    }
+
+    pub fn traverse(&self, ray: &Ray3d) -> Option<HitRecord> {
+        traverse(&self.nodes, ray, |leaf, ray| get_nearest_from_leaf(self.primitives, leaf, ray))
+    }
+
+    /// Tells whether any primitive is hit by the ray within its interval cut at t_max, e.g.
+    /// whether a shadow ray is blocked on its way to the light
+    pub fn occluded(&self, ray: &Ray3d, t_max: f32) -> bool {
+        is_occluded(&self.nodes, ray, t_max, |leaf, ray| {
+            is_occluded_in_leaf(self.primitives, leaf, ray)
+        })
+    }
+
+    pub fn get_sah_cost(&self) -> f32 {
+        get_sah_cost(&self.nodes)
+    }
+}
+
+impl<'a, P, const N: usize> std::fmt::Display for ParOctree<'a, P, N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fmt_nodes(&self.nodes, f)
+    }
+}
+
+/// Owning counterpart of [`Octree`] and [`ParOctree`]: keeps its own copy of the primitives,
+/// reordered so that the primitives of each leaf are stored contiguously. It does not borrow
+/// the primitive slice it was built from, so it can be stored next to it. Traversal still
+/// reports primitives by their indices in the original slice.
+pub struct OwnedOctree<P, const N: usize> {
+    nodes: Vec<OctreeNode<PackedLeafNode>>,
+    primitives: Vec<P>,
+    primitives_idx: Vec<usize>,
+}
+
+impl<P, const N: usize> OwnedOctree<P, N>
+where
+    P: TraceablePrimitive + Copy + Send + Sync,
+{
+    pub fn new(primitives: &[P]) -> OwnedOctree<P, N> {
+        OctreeBuilder::new().build(primitives)
+    }
+
+    // The hit refers to the primitive by its original index, not by its position in the packed
+    // storage
+    fn get_nearest_from_leaf(&self, leaf: &PackedLeafNode, ray: &Ray3d) -> Option<HitRecord> {
+        let mut ray = *ray;
+        let mut nearest: Option<HitRecord> = None;
+        for pos in leaf.first..leaf.first + leaf.len {
+            if let Some(hit) = self.primitives[pos].intersect(&ray) {
+                ray = ray.with_t_max(hit.distance);
+                nearest = Some(hit.with_primitive_id(self.primitives_idx[pos]));
+            }
+        }
+        nearest
+    }
+
+    pub fn traverse(&self, ray: &Ray3d) -> Option<HitRecord> {
+        traverse(&self.nodes, ray, |leaf, ray| self.get_nearest_from_leaf(leaf, ray))
+    }
+
+    /// Tells whether any primitive is hit by the ray within its interval cut at t_max, e.g.
+    /// whether a shadow ray is blocked on its way to the light
+    pub fn occluded(&self, ray: &Ray3d, t_max: f32) -> bool {
+        is_occluded(&self.nodes, ray, t_max, |leaf, ray| {
+            self.primitives[leaf.first..leaf.first + leaf.len]
+                .iter()
+                .any(|prim| prim.get_distance_to(ray) != None)
+        })
+    }
+
+    pub fn get_sah_cost(&self) -> f32 {
+        get_sah_cost(&self.nodes)
+    }
+}
+
+impl<P: Copy, const N: usize> OwnedOctree<P, N> {
+    // Copies the primitives of every leaf next to each other, in the order the leaves are stored
+    fn pack(nodes: Vec<OctreeNode<OctreeLeafNode<N>>>, primitives: &[P]) -> OwnedOctree<P, N> {
+        let mut packed_primitives = Vec::with_capacity(primitives.len());
+        let mut primitives_idx = Vec::with_capacity(primitives.len());
+
+        let nodes = nodes
+            .into_iter()
+            .map(|node| match node {
                 OctreeNode::Leaf(leaf) => {
-                    write!(f, " {}, items:[", leaf.bb);
-                    for i in leaf.items_idx {
-                        match i {
-                            None => write!(f, "None,"),
-                            Some(item) => write!(f, "{},", item),
-                        };
-                    }
-                    writeln!(f, "]");
+                    let first = packed_primitives.len();
+                    leaf.items_idx.iter().filter_map(|&x| x).for_each(|idx| {
+                        packed_primitives.push(primitives[idx]);
+                        primitives_idx.push(idx);
+                    });
+                    OctreeNode::Leaf(PackedLeafNode {
+                        bb: leaf.bb,
+                        first,
+                        len: packed_primitives.len() - first,
+                    })
                 }
-            }
+                OctreeNode::Inner(inner) => OctreeNode::Inner(inner),
+            })
+            .collect();
+
+        OwnedOctree {
+            nodes,
+            primitives: packed_primitives,
+            primitives_idx,
         }
-        write!(f, "Done!")
     }
 }
 
+impl<'a, P: Copy, const N: usize> From<Octree<'a, P, N>> for OwnedOctree<P, N> {
+    fn from(octree: Octree<'a, P, N>) -> Self {
+        OwnedOctree::pack(octree.nodes, octree.primitives)
+    }
+}
+
+impl<'a, P: Copy, const N: usize> From<ParOctree<'a, P, N>> for OwnedOctree<P, N> {
+    fn from(octree: ParOctree<'a, P, N>) -> Self {
+        OwnedOctree::pack(octree.nodes, octree.primitives)
+    }
+}
+
+impl<P, const N: usize> std::fmt::Display for OwnedOctree<P, N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fmt_nodes(&self.nodes, f)
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use geometry::TraceablePrimitive;
+    use geometry::{Motion, TraceablePrimitive};
 
     const golden_ref: [Point3d; 9] = [
         Point3d {x: 0.0, y: 0.0, z: 0.0},
@@ -420,6 +956,259 @@ mod tests {
         println!("{}", octree);
     }
 
+    #[test]
+    fn t_par_octree_build_leaf_cap_1() {
+        const LEAF_CAPACITY: usize = 1;
+        let octree = Octree::<Point3d, LEAF_CAPACITY>::new(&golden_ref);
+        let par_octree = ParOctree::<Point3d, LEAF_CAPACITY>::new(&golden_ref);
+
+        // Both split at the same bits of the Morton codes, the trees match node for node
+        assert_eq!(par_octree.nodes, octree.nodes);
+        let is_leaf = |x: &&OctreeNode<_>| matches!(x, OctreeNode::Leaf(_));
+        let num_leaves = par_octree.nodes.iter().filter(is_leaf).count();
+        assert_eq!(num_leaves, golden_ref.len());
+    }
+
+    #[test]
+    fn t_par_octree_same_key() {
+        const LEAF_CAPACITY: usize = 2;
+        let primitives = [Point3d::from_coords(1.0, 1.0, 1.0); 9];
+        let octree = ParOctree::<Point3d, LEAF_CAPACITY>::new(&primitives);
+
+        let leaf_items: usize = octree
+            .nodes
+            .iter()
+            .map(|node| match node {
+                OctreeNode::Leaf(leaf) => leaf.items_idx.iter().filter(|x| x.is_some()).count(),
+                OctreeNode::Inner(_) => 0,
+            })
+            .sum();
+        assert_eq!(leaf_items, primitives.len());
+    }
+
+    // Pseudo-random triangles scattered in front of the origin
+    fn get_random_triangles(num: usize) -> Vec<Triangle> {
+        let mut seed: u32 = 12345;
+        let mut rand = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+        (0..num)
+            .map(|_| {
+                let center = Point3d::from_coords(
+                    rand() * 40.0 - 20.0,
+                    rand() * 40.0 - 20.0,
+                    rand() * -40.0 - 10.0,
+                );
+                Triangle::new(
+                    center + Vector3d::from_coords(-rand(), -rand(), rand() - 0.5),
+                    center + Vector3d::from_coords(rand(), -rand(), rand() - 0.5),
+                    center + Vector3d::from_coords(rand() - 0.5, rand(), rand() - 0.5),
+                )
+            })
+            .collect()
+    }
+
+    // Casts a grid of rays from the origin and checks that both traversals find the same hits
+    fn compare_traversals<F, G>(reference: F, tested: G)
+    where
+        F: Fn(&Ray3d) -> Option<HitRecord>,
+        G: Fn(&Ray3d) -> Option<HitRecord>,
+    {
+        let origin = Point3d::new();
+        let mut num_hits = 0;
+        for y in -50..50 {
+            for x in -50..50 {
+                let ray_dir = Vector3d::from_coords(x as f32 * 0.01, y as f32 * 0.01, -1.0);
+                let ray = Ray3d::from(origin, ray_dir.normalize());
+                let nearest = reference(&ray);
+                assert_eq!(nearest, tested(&ray));
+                if nearest != None {
+                    num_hits += 1;
+                }
+            }
+        }
+        assert!(num_hits > 0);
+    }
+
+    #[test]
+    fn t_par_octree_traverse() {
+        let triangles = get_random_triangles(10000);
+        let octree = Octree::<Triangle, 8>::new(&triangles);
+        let par_octree = ParOctree::<Triangle, 8>::new(&triangles);
+
+        compare_traversals(|ray| octree.traverse(ray), |ray| par_octree.traverse(ray));
+    }
+
+    #[test]
+    fn t_owned_octree_traverse() {
+        let triangles = get_random_triangles(10000);
+        let octree = Octree::<Triangle, 8>::new(&triangles);
+        // The owned tree must outlive the primitives it was built from
+        let owned_octree = {
+            let triangles = triangles.clone();
+            OwnedOctree::<Triangle, 8>::new(&triangles)
+        };
+
+        compare_traversals(|ray| octree.traverse(ray), |ray| owned_octree.traverse(ray));
+    }
+
+    #[test]
+    fn t_traverse_interval() {
+        let triangles = get_random_triangles(10000);
+        let octree = OwnedOctree::<Triangle, 8>::new(&triangles);
+
+        // Checks the traversal against testing every triangle
+        let brute_force = |ray: &Ray3d| {
+            triangles
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, t)| t.intersect(ray).map(|hit| hit.with_primitive_id(idx)))
+                .fold(None, |nearest: Option<HitRecord>, hit| match nearest {
+                    Some(nearest_hit) if nearest_hit.distance <= hit.distance => nearest,
+                    _ => Some(hit),
+                })
+        };
+
+        let origin = Point3d::new();
+        for y in -20..20 {
+            for x in -20..20 {
+                let ray_dir = Vector3d::from_coords(x as f32 * 0.025, y as f32 * 0.025, -1.0);
+                let ray = Ray3d::from(origin, ray_dir.normalize());
+                let nearest = octree.traverse(&ray);
+                assert_eq!(nearest, brute_force(&ray));
+
+                if let Some(hit) = nearest {
+                    // Nothing is in front of the nearest hit
+                    assert_eq!(octree.traverse(&ray.with_t_max(hit.distance - 0.01)), None);
+                    // Skip the nearest hit and look for the next one
+                    let ray = ray.with_interval(hit.distance + 0.01, f32::MAX);
+                    assert_eq!(octree.traverse(&ray), brute_force(&ray));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn t_occluded() {
+        let triangles = get_random_triangles(10000);
+        let octree = Octree::<Triangle, 8>::new(&triangles);
+        let par_octree = ParOctree::<Triangle, 8>::new(&triangles);
+        let owned_octree = OwnedOctree::<Triangle, 8>::new(&triangles);
+
+        let origin = Point3d::new();
+        for y in -50..50 {
+            for x in -50..50 {
+                let ray_dir = Vector3d::from_coords(x as f32 * 0.01, y as f32 * 0.01, -1.0);
+                let ray = Ray3d::from(origin, ray_dir.normalize());
+                match octree.traverse(&ray).map(|hit| hit.distance) {
+                    Some(dist) => {
+                        assert!(octree.occluded(&ray, dist + 0.01));
+                        assert!(par_octree.occluded(&ray, dist + 0.01));
+                        assert!(owned_octree.occluded(&ray, dist + 0.01));
+                        assert!(!octree.occluded(&ray, dist - 0.01));
+                        assert!(!par_octree.occluded(&ray, dist - 0.01));
+                        assert!(!owned_octree.occluded(&ray, dist - 0.01));
+                    }
+                    None => {
+                        assert!(!octree.occluded(&ray, f32::MAX));
+                        assert!(!par_octree.occluded(&ray, f32::MAX));
+                        assert!(!owned_octree.occluded(&ray, f32::MAX));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn t_sah_octree_traverse() {
+        let triangles = get_random_triangles(10000);
+        let octree = Octree::<Triangle, 8>::new(&triangles);
+
+        let sah_octree = OctreeBuilder::new()
+            .split_method(SplitMethod::BinnedSah { num_bins: 16 })
+            .build::<Triangle, 8>(&triangles);
+        compare_traversals(|ray| octree.traverse(ray), |ray| sah_octree.traverse(ray));
+
+        let hybrid_octree = OctreeBuilder::new()
+            .split_method(SplitMethod::Hybrid {
+                num_bins: 16,
+                max_morton_len: 256,
+            })
+            .build::<Triangle, 8>(&triangles);
+        compare_traversals(|ray| octree.traverse(ray), |ray| hybrid_octree.traverse(ray));
+    }
+
+    #[test]
+    fn t_motion_traverse() {
+        // Every triangle sweeps sideways over the shutter interval
+        let moving = get_random_triangles(10000)
+            .into_iter()
+            .map(|t| {
+                let offset = Vector3d::from_coords(0.5, 0.0, 0.0);
+                Motion::new(t, Triangle::new(t.v[0] + offset, t.v[1] + offset, t.v[2] + offset))
+            })
+            .collect::<Vec<Motion<Triangle>>>();
+        let octree = OwnedOctree::<Motion<Triangle>, 8>::new(&moving);
+
+        for time in [0.0, 0.3, 1.0] {
+            let triangles = moving.iter().map(|m| m.get_at(time)).collect::<Vec<Triangle>>();
+            let reference = OwnedOctree::<Triangle, 8>::new(&triangles);
+            compare_traversals(
+                |ray| reference.traverse(&ray.with_time(time)),
+                |ray| octree.traverse(&ray.with_time(time)),
+            );
+        }
+    }
+
+    #[test]
+    fn t_sah_cost() {
+        // One big triangle next to a dense cluster of small ones
+        let mut triangles = get_random_triangles(2000)
+            .into_iter()
+            .map(|t| Triangle::new(t.v[0] * 0.1, t.v[1] * 0.1, t.v[2] * 0.1))
+            .collect::<Vec<Triangle>>();
+        triangles.push(Triangle::new(
+            Point3d::from_coords(-100.0, -100.0, -60.0),
+            Point3d::from_coords(100.0, -100.0, -60.0),
+            Point3d::from_coords(0.0, 100.0, -60.0),
+        ));
+
+        let morton_cost = OwnedOctree::<Triangle, 4>::new(&triangles).get_sah_cost();
+        let sah_cost = OctreeBuilder::new()
+            .split_method(SplitMethod::BinnedSah { num_bins: 16 })
+            .build::<Triangle, 4>(&triangles)
+            .get_sah_cost();
+        assert!(sah_cost > 0.0);
+        assert!(sah_cost < morton_cost);
+    }
+
+    #[test]
+    fn t_hybrid_sah_cost() {
+        let triangles = get_random_triangles(10000);
+        let get_cost = |split_method: SplitMethod| {
+            OctreeBuilder::new()
+                .split_method(split_method)
+                .build::<Triangle, 8>(&triangles)
+                .get_sah_cost()
+        };
+        let get_hybrid_cost = |max_morton_len: usize| {
+            get_cost(SplitMethod::Hybrid {
+                num_bins: 16,
+                max_morton_len,
+            })
+        };
+        let morton_cost = get_cost(SplitMethod::Morton);
+        let sah_cost = get_cost(SplitMethod::BinnedSah { num_bins: 16 });
+
+        // The two ends are the pure methods
+        assert_eq!(get_hybrid_cost(8), sah_cost);
+        assert_eq!(get_hybrid_cost(triangles.len()), morton_cost);
+        let hybrid_cost = get_hybrid_cost(64);
+        assert!(hybrid_cost <= morton_cost);
+        assert!(sah_cost <= hybrid_cost);
+    }
+
     // #[test]
     // fn t_octree_build_leaf_cap_2() {
     //     const LEAF_CAPACITY: usize = 2;
@@ -0,0 +1,387 @@
+use rayon::prelude::*;
+
+use crate::framebuffer::Framebuffer;
+use crate::scene::random::random_f32;
+use crate::scene::Color;
+
+/// Where the samples of a pixel go
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SamplePattern {
+    /// Centers of an n x n grid
+    Stratified { n: usize },
+    /// Random points within the cells of an n x n grid
+    Jittered { n: usize },
+    /// Halton sequence in bases 2 and 3, randomly shifted for every pixel
+    Halton { num_samples: usize },
+    /// (0, 2)-sequence Sobol points, randomly scrambled for every pixel
+    Sobol { num_samples: usize },
+    /// Sobol samples in batches of min_samples until the standard error of the pixel's luminance
+    /// drops below max_error or max_samples are taken
+    Adaptive {
+        min_samples: usize,
+        max_samples: usize,
+        max_error: f32,
+    },
+}
+
+/// Weights the samples by their distance from the pixel center, in pixels. The samples are not
+/// shared between the pixels, every pixel spreads its own ones over the whole support of the
+/// filter: the wider it is, the fewer of them land within the pixel itself.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PixelFilter {
+    Box { radius: f32 },
+    Tent { radius: f32 },
+    Gaussian { radius: f32, alpha: f32 },
+    /// B = C = 1/3 is the recommended trade-off between blurring and ringing
+    MitchellNetravali { radius: f32, b: f32, c: f32 },
+}
+
+impl PixelFilter {
+    pub fn get_radius(&self) -> f32 {
+        match *self {
+            PixelFilter::Box { radius } => radius,
+            PixelFilter::Tent { radius } => radius,
+            PixelFilter::Gaussian { radius, .. } => radius,
+            PixelFilter::MitchellNetravali { radius, .. } => radius,
+        }
+    }
+
+    /// Separable, the product of the 1D filter along both the axes
+    pub fn eval(&self, dx: f32, dy: f32) -> f32 {
+        self.eval_1d(dx) * self.eval_1d(dy)
+    }
+
+    fn eval_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match *self {
+            PixelFilter::Box { radius } => {
+                if x <= radius {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            PixelFilter::Tent { radius } => (radius - x).max(0.0),
+            // Shifted down so that it reaches zero at the radius
+            PixelFilter::Gaussian { radius, alpha } => {
+                ((-alpha * x * x).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+            PixelFilter::MitchellNetravali { radius, b, c } => {
+                let x = 2.0 * x / radius;
+                let y = if x < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b)
+                } else if x < 2.0 {
+                    (-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c)
+                } else {
+                    0.0
+                };
+                y / 6.0
+            }
+        }
+    }
+}
+
+/// Renders every pixel from several samples spread over the support of the pixel filter. By
+/// default a 4 x 4 jittered grid within the pixel, box filtered.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Supersampler {
+    pattern: SamplePattern,
+    filter: PixelFilter,
+}
+
+impl Supersampler {
+    pub fn new() -> Self {
+        Supersampler {
+            pattern: SamplePattern::Jittered { n: 4 },
+            filter: PixelFilter::Box { radius: 0.5 },
+        }
+    }
+
+    pub fn pattern(mut self, pattern: SamplePattern) -> Self {
+        self.pattern = pattern;
+        self
+    }
+    pub fn filter(mut self, filter: PixelFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Fills the framebuffer, radiance is given the pixel and the sample offset within it, the way
+    /// [`Camera::generate_ray`](crate::camera::Camera::generate_ray) takes them. Offsets reach
+    /// outside of [0; 1) when the filter is wider than the pixel.
+    pub fn render<F>(&self, fbuf: &mut Framebuffer, radiance: F)
+    where
+        F: Fn([u32; 2], [f32; 2]) -> Color + Sync,
+    {
+        let width = fbuf.get_width();
+        fbuf.get_pixels_mut().par_iter_mut().enumerate().for_each(|(idx, pix)| {
+            let pixel = [idx as u32 % width, idx as u32 / width];
+            *pix = self.render_pixel(pixel, &radiance);
+        });
+    }
+
+    fn render_pixel<F>(&self, pixel: [u32; 2], radiance: &F) -> Color
+    where
+        F: Fn([u32; 2], [f32; 2]) -> Color,
+    {
+        // The samples cover the filter support, at least the pixel itself
+        let extent = self.filter.get_radius().max(0.5);
+        let mut sum = Color::black();
+        let mut weight_sum = 0.0;
+        let mut add_sample = |sx: f32, sy: f32| -> Color {
+            let (dx, dy) = ((sx - 0.5) * 2.0 * extent, (sy - 0.5) * 2.0 * extent);
+            let color = radiance(pixel, [0.5 + dx, 0.5 + dy]);
+            let weight = self.filter.eval(dx, dy);
+            sum += color * weight;
+            weight_sum += weight;
+            color
+        };
+
+        match self.pattern {
+            SamplePattern::Stratified { n } => {
+                for (sx, sy) in get_grid(n, || 0.5) {
+                    add_sample(sx, sy);
+                }
+            }
+            SamplePattern::Jittered { n } => {
+                for (sx, sy) in get_grid(n, random_f32) {
+                    add_sample(sx, sy);
+                }
+            }
+            SamplePattern::Halton { num_samples } => {
+                let (shift_x, shift_y) = (random_f32(), random_f32());
+                for i in 0..num_samples {
+                    let sx = (radical_inverse(i as u32, 2) + shift_x).fract();
+                    let sy = (radical_inverse(i as u32, 3) + shift_y).fract();
+                    add_sample(sx, sy);
+                }
+            }
+            SamplePattern::Sobol { num_samples } => {
+                let scramble = get_sobol_scramble();
+                for i in 0..num_samples {
+                    let (sx, sy) = get_sobol(i as u32, scramble);
+                    add_sample(sx, sy);
+                }
+            }
+            SamplePattern::Adaptive {
+                min_samples,
+                max_samples,
+                max_error,
+            } => {
+                let scramble = get_sobol_scramble();
+                let (mut mean, mut m2) = (0.0_f32, 0.0_f32); // Welford's running variance
+                let mut i = 0;
+                while i < max_samples {
+                    let (sx, sy) = get_sobol(i as u32, scramble);
+                    let luminance = add_sample(sx, sy).get_luminance();
+                    i += 1;
+                    let delta = luminance - mean;
+                    mean += delta / i as f32;
+                    m2 += delta * (luminance - mean);
+
+                    if i >= min_samples.max(2) && i % min_samples.max(1) == 0 {
+                        let std_error = (m2 / ((i - 1) * i) as f32).sqrt();
+                        if std_error < max_error {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        if weight_sum.abs() > f32::EPSILON {
+            sum / weight_sum
+        } else {
+            sum
+        }
+    }
+}
+
+impl Default for Supersampler {
+    fn default() -> Self {
+        Supersampler::new()
+    }
+}
+
+// Points of an n x n grid in the unit square, offset within their cells
+fn get_grid<O>(n: usize, offset: O) -> impl Iterator<Item = (f32, f32)>
+where
+    O: Fn() -> f32,
+{
+    let n = n.max(1);
+    (0..n * n).map(move |i| {
+        let (cx, cy) = ((i % n) as f32, (i / n) as f32);
+        ((cx + offset()) / n as f32, (cy + offset()) / n as f32)
+    })
+}
+
+// The digits of i in the base mirrored around the decimal point
+fn radical_inverse(mut i: u32, base: u32) -> f32 {
+    let inv_base = 1.0 / base as f32;
+    let mut factor = inv_base;
+    let mut result = 0.0;
+    while i > 0 {
+        result += (i % base) as f32 * factor;
+        i /= base;
+        factor *= inv_base;
+    }
+    result
+}
+
+fn get_sobol_scramble() -> (u32, u32) {
+    let random_u32 = || (random_f32() * (1u64 << 32) as f32) as u64 as u32;
+    (random_u32(), random_u32())
+}
+
+// Point i of the first two Sobol dimensions, XOR-scrambled: the first is the van der Corput
+// sequence, the second has the generator matrix of the polynomial x + 1
+fn get_sobol(mut i: u32, scramble: (u32, u32)) -> (f32, f32) {
+    let x = i.reverse_bits() ^ scramble.0;
+    let mut y = scramble.1;
+    let mut v = 1u32 << 31;
+    while i != 0 {
+        if i & 1 != 0 {
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    let to_unit = |x: u32| (x >> 8) as f32 / (1u32 << 24) as f32;
+    (to_unit(x), to_unit(y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+
+    const FILTERS: [PixelFilter; 5] = [
+        PixelFilter::Box { radius: 0.5 },
+        PixelFilter::Tent { radius: 1.0 },
+        PixelFilter::Gaussian { radius: 1.5, alpha: 2.0 },
+        PixelFilter::MitchellNetravali { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 },
+        // Negative lobes, the weights may not even be all positive
+        PixelFilter::MitchellNetravali { radius: 2.0, b: 0.0, c: 0.5 },
+    ];
+
+    const PATTERNS: [SamplePattern; 5] = [
+        SamplePattern::Stratified { n: 3 },
+        SamplePattern::Jittered { n: 4 },
+        SamplePattern::Halton { num_samples: 16 },
+        SamplePattern::Sobol { num_samples: 16 },
+        SamplePattern::Adaptive { min_samples: 4, max_samples: 64, max_error: 0.01 },
+    ];
+
+    #[test]
+    fn t_radical_inverse() {
+        let base_2: Vec<f32> = (0..5).map(|i| radical_inverse(i, 2)).collect();
+        assert_eq!(base_2, vec![0.0, 0.5, 0.25, 0.75, 0.125]);
+        assert!((radical_inverse(1, 3) - 1.0 / 3.0).abs() < 1e-6);
+        assert!((radical_inverse(3, 3) - 1.0 / 9.0).abs() < 1e-6);
+        // 5 is 12 in base 3, mirrored 0.21
+        assert!((radical_inverse(5, 3) - 7.0 / 9.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn t_sobol() {
+        let points: Vec<(f32, f32)> = (0..8).map(|i| get_sobol(i, (0, 0))).collect();
+        let x: Vec<f32> = points.iter().map(|p| p.0).collect();
+        let y: Vec<f32> = points.iter().map(|p| p.1).collect();
+        assert_eq!(x, vec![0.0, 0.5, 0.25, 0.75, 0.125, 0.625, 0.375, 0.875]);
+        assert_eq!(y, vec![0.0, 0.5, 0.75, 0.25, 0.625, 0.125, 0.375, 0.875]);
+
+        // A (0, 2)-sequence: the first 16 points fall one into each of the 4 x 4 cells, the
+        // 16 columns and the 16 rows, scrambled or not
+        for scramble in [(0, 0), get_sobol_scramble(), get_sobol_scramble()] {
+            let points: Vec<(f32, f32)> = (0..16).map(|i| get_sobol(i, scramble)).collect();
+            for (nx, ny) in [(4, 4), (16, 1), (1, 16)] {
+                let mut cells = [0; 16];
+                for (x, y) in &points {
+                    assert!((0.0..1.0).contains(x) && (0.0..1.0).contains(y));
+                    let (cx, cy) = ((x * nx as f32) as usize, (y * ny as f32) as usize);
+                    cells[cy * nx + cx] += 1;
+                }
+                assert!(cells.iter().all(|&x| x == 1), "{:?} in {}x{}", scramble, nx, ny);
+            }
+        }
+    }
+
+    #[test]
+    fn t_filters() {
+        for filter in FILTERS {
+            let radius = filter.get_radius();
+            assert!(filter.eval(0.0, 0.0) > 0.0);
+            assert_eq!(filter.eval(radius + 0.01, 0.0), 0.0);
+            assert_eq!(filter.eval(0.0, -radius - 0.01), 0.0);
+            assert_eq!(filter.eval(0.3, 0.2), filter.eval(-0.3, 0.2));
+        }
+        assert_eq!(PixelFilter::Tent { radius: 1.0 }.eval(0.5, 0.5), 0.25);
+        // Mitchell-Netravali with B = 0 has negative lobes
+        let mitchell = PixelFilter::MitchellNetravali { radius: 2.0, b: 0.0, c: 0.5 };
+        assert!(mitchell.eval(1.5, 0.0) < 0.0);
+    }
+
+    #[test]
+    fn t_filter_normalization() {
+        // The weights are normalized, a flat image stays the same whatever the filter
+        for filter in FILTERS {
+            for pattern in PATTERNS {
+                let supersampler = Supersampler::new().pattern(pattern).filter(filter);
+                let color = supersampler.render_pixel([3, 7], &|_, _| Color::new(0.2, 0.5, 4.0));
+                assert!((color.r - 0.2).abs() < 1e-4, "{:?} {:?}", filter, pattern);
+                assert!((color.g - 0.5).abs() < 1e-4, "{:?} {:?}", filter, pattern);
+                assert!((color.b - 4.0).abs() < 1e-4, "{:?} {:?}", filter, pattern);
+            }
+        }
+    }
+
+    #[test]
+    fn t_default_samples_within_pixel() {
+        let offsets = RefCell::new(Vec::new());
+        Supersampler::new().render_pixel([0, 0], &|_, offset| {
+            offsets.borrow_mut().push(offset);
+            Color::black()
+        });
+        let offsets = offsets.into_inner();
+        assert_eq!(offsets.len(), 16);
+        for [x, y] in offsets {
+            assert!((0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y));
+        }
+    }
+
+    #[test]
+    fn t_adaptive_stopping() {
+        let pattern = SamplePattern::Adaptive { min_samples: 8, max_samples: 64, max_error: 0.01 };
+        let supersampler = Supersampler::new().pattern(pattern);
+        let num_samples = Cell::new(0);
+
+        // No variance, done after the first batch
+        supersampler.render_pixel([0, 0], &|_, _| {
+            num_samples.set(num_samples.get() + 1);
+            Color::white()
+        });
+        assert_eq!(num_samples.get(), 8);
+
+        // Too noisy to ever get below the error, all the samples are taken
+        num_samples.set(0);
+        supersampler.render_pixel([0, 0], &|_, _| {
+            num_samples.set(num_samples.get() + 1);
+            Color::grey((num_samples.get() % 2) as f32 * 10.0)
+        });
+        assert_eq!(num_samples.get(), 64);
+
+        // A little noise takes a few batches, the stop is always at the end of one
+        num_samples.set(0);
+        supersampler.render_pixel([0, 0], &|_, _| {
+            num_samples.set(num_samples.get() + 1);
+            Color::grey(1.0 + (num_samples.get() % 2) as f32 * 0.1)
+        });
+        // Standard error 0.05 / sqrt(n - 1) drops below 0.01 past 26 samples
+        assert_eq!(num_samples.get(), 32);
+    }
+}
@@ -0,0 +1,239 @@
+use geometry::{Point3d, Ray3d, Vector3d};
+
+use crate::scene::random::random_f32;
+
+mod equirectangular;
+mod fisheye;
+mod orthographic;
+mod perspective;
+
+pub use self::equirectangular::EquirectangularCamera;
+pub use self::fisheye::FisheyeCamera;
+pub use self::orthographic::OrthographicCamera;
+pub use self::perspective::PerspectiveCamera;
+
+/// Projection of the scene onto the image, the camera models are interchangeable through it.
+/// Pixel rows go from the bottom of the image up, like in
+/// [`Framebuffer`](crate::framebuffer::Framebuffer).
+pub trait Camera: Send + Sync {
+    /// Ray through the pixel, sample_offset being the position within it: (0, 0) is the bottom
+    /// left corner, (1, 1) the top right one. Offsets outside of that reach into the neighbouring
+    /// pixels, wide pixel filters need it. The ray is given a random time within the shutter
+    /// interval.
+    fn generate_ray(&self, pixel: [u32; 2], sample_offset: [f32; 2]) -> Ray3d;
+
+    /// Angle between the rays of neighbouring pixels in the middle of the image, 0.0 when they
+    /// are parallel
+    fn get_pixel_spread_angle(&self) -> f32;
+}
+
+// Placement of the camera and the image, the same for all the models
+#[derive(Copy, Clone, Debug)]
+struct Frame {
+    eye: Point3d,
+    // Orthonormal basis, the camera looks along forward
+    right: Vector3d,
+    up: Vector3d,
+    forward: Vector3d,
+    // Width to height of the image plane
+    aspect: f32,
+    // Distances from the eye beyond which nothing is seen
+    near: f32,
+    far: f32,
+    // Part of the [0; 1] interval between the start and the end transforms of the moving objects
+    // when the shutter is open
+    shutter: [f32; 2],
+    width: u32,
+    height: u32,
+}
+
+impl Frame {
+    // At the origin looking down -Z, the aspect ratio that of the image
+    fn new(width: u32, height: u32) -> Self {
+        Frame {
+            eye: Point3d::from_coords(0.0, 0.0, 0.0),
+            right: Vector3d::from_coords(1.0, 0.0, 0.0),
+            up: Vector3d::from_coords(0.0, 1.0, 0.0),
+            forward: Vector3d::from_coords(0.0, 0.0, -1.0),
+            aspect: width as f32 / height as f32,
+            near: 0.0,
+            far: f32::MAX,
+            shutter: [0.0, 0.0],
+            width,
+            height,
+        }
+    }
+
+    // An up along the viewing direction is replaced with the axis furthest from it
+    fn look_at(&mut self, eye: Point3d, target: Point3d, up: Vector3d) {
+        self.eye = eye;
+        self.forward = (target - eye).normalize();
+        let mut right = self.forward.crossprod(&up);
+        if right.len() < 1e-6 {
+            let f = self.forward;
+            let axis = if f.x.abs() <= f.y.abs() && f.x.abs() <= f.z.abs() {
+                Vector3d::from_coords(1.0, 0.0, 0.0)
+            } else if f.y.abs() <= f.z.abs() {
+                Vector3d::from_coords(0.0, 1.0, 0.0)
+            } else {
+                Vector3d::from_coords(0.0, 0.0, 1.0)
+            };
+            right = f.crossprod(&axis);
+        }
+        self.right = right.normalize();
+        self.up = self.right.crossprod(&self.forward);
+    }
+
+    // Position on the image mapped to [-1; 1] along both the axes
+    fn get_ndc(&self, pixel: [u32; 2], sample_offset: [f32; 2]) -> (f32, f32) {
+        let x = 2.0 * (pixel[0] as f32 + sample_offset[0]) / self.width as f32 - 1.0;
+        let y = 2.0 * (pixel[1] as f32 + sample_offset[1]) / self.height as f32 - 1.0;
+        (x, y)
+    }
+
+    // Ray at a random moment within the shutter interval
+    fn new_ray(&self, origin: Point3d, direction: Vector3d) -> Ray3d {
+        Ray3d::from(origin, direction).with_time(self.sample_time())
+    }
+
+    fn sample_time(&self) -> f32 {
+        let [open, close] = self.shutter;
+        if open == close { open } else { open + (close - open) * random_f32() }
+    }
+
+    // Camera space vector in world space
+    fn get_world_vector(&self, x: f32, y: f32, z: f32) -> Vector3d {
+        self.right * x + self.up * y + self.forward * z
+    }
+
+    // Restricts the ray to the part between the clipping planes, near and far being measured along
+    // the viewing direction
+    fn clip_planar(&self, ray: Ray3d) -> Ray3d {
+        let cos = ray.get_direction() * self.forward;
+        let far = if self.far == f32::MAX { f32::MAX } else { self.far / cos };
+        ray.with_interval(self.near / cos, far)
+    }
+
+    // Restricts the ray to the part between the clipping spheres around the eye, for the models
+    // seeing behind themselves
+    fn clip_radial(&self, ray: Ray3d) -> Ray3d {
+        ray.with_interval(self.near, self.far)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn assert_vector(actual: Vector3d, expected: Vector3d) {
+        assert!((actual - expected).len() < EPSILON, "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn t_look_at() {
+        let mut frame = Frame::new(2, 2);
+        let eye = Point3d::from_coords(1.0, 2.0, 3.0);
+        let target = Point3d::from_coords(4.0, -2.0, 3.0);
+        frame.look_at(eye, target, Vector3d::from_coords(0.0, 1.0, 0.0));
+        assert_vector(frame.forward, Vector3d::from_coords(0.6, -0.8, 0.0));
+        assert_vector(frame.right, Vector3d::from_coords(0.0, 0.0, 1.0));
+        assert_vector(frame.up, Vector3d::from_coords(0.8, 0.6, 0.0));
+
+        // Straight up and down with the default up still give an orthonormal basis
+        let up = Vector3d::from_coords(0.0, 1.0, 0.0);
+        for y in [10.0, -10.0] {
+            frame.look_at(eye, Point3d::from_coords(1.0, y, 3.0), up);
+            let (right, up, forward) = (frame.right, frame.up, frame.forward);
+            for v in [right, up, forward] {
+                assert!((v.len() - 1.0).abs() < EPSILON);
+            }
+            assert!((right * up).abs() < EPSILON && (up * forward).abs() < EPSILON);
+            assert!((forward * right).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn t_center_ray() {
+        let eye = Point3d::from_coords(1.0, 2.0, 3.0);
+        let target = Point3d::from_coords(4.0, -2.0, 3.0);
+        let up = Vector3d::from_coords(0.0, 1.0, 0.0);
+        let cameras: [Box<dyn Camera>; 3] = [
+            Box::new(PerspectiveCamera::new(3, 3).look_at(eye, target, up)),
+            Box::new(FisheyeCamera::new(3, 3).look_at(eye, target, up)),
+            Box::new(EquirectangularCamera::new(3, 3).look_at(eye, target, up)),
+        ];
+        for camera in &cameras {
+            let ray = camera.generate_ray([1, 1], [0.5, 0.5]);
+            assert_eq!(ray.get_origin(), eye);
+            assert_vector(ray.get_direction(), Vector3d::from_coords(0.6, -0.8, 0.0));
+        }
+
+        let camera = OrthographicCamera::new(3, 3).look_at(eye, target, up);
+        let ray = camera.generate_ray([1, 1], [0.5, 0.5]);
+        assert_vector(ray.get_origin() - eye, Vector3d::new());
+        assert_vector(ray.get_direction(), Vector3d::from_coords(0.6, -0.8, 0.0));
+    }
+
+    #[test]
+    fn t_clip() {
+        // Planar clipping measures along the viewing direction, the ray to the corner is longer
+        let camera = PerspectiveCamera::new(2, 2).fov(90.0).clip(1.0, 10.0);
+        let ray = camera.generate_ray([1, 1], [0.0, 0.0]);
+        assert_eq!((ray.get_t_min(), ray.get_t_max()), (1.0, 10.0));
+        let ray = camera.generate_ray([0, 1], [0.0, 0.0]);
+        let sqrt_2 = 2f32.sqrt();
+        assert!((ray.get_t_min() - sqrt_2).abs() < EPSILON);
+        assert!((ray.get_t_max() - 10.0 * sqrt_2).abs() < EPSILON);
+
+        let camera = OrthographicCamera::new(2, 2).clip(1.0, 10.0);
+        let ray = camera.generate_ray([0, 1], [0.0, 0.0]);
+        assert_eq!((ray.get_t_min(), ray.get_t_max()), (1.0, 10.0));
+
+        // Radial clipping is the same for all the rays
+        let camera = FisheyeCamera::new(2, 2).clip(1.0, 10.0);
+        for pixel in [[0, 0], [1, 1]] {
+            let ray = camera.generate_ray(pixel, [0.0, 0.0]);
+            assert_eq!((ray.get_t_min(), ray.get_t_max()), (1.0, 10.0));
+        }
+
+        // Unclipped rays reach the infinity
+        let ray = PerspectiveCamera::new(2, 2).generate_ray([0, 1], [0.0, 0.0]);
+        assert_eq!(ray.get_t_max(), f32::MAX);
+    }
+
+    #[test]
+    fn t_thin_lens() {
+        let eye = Point3d::from_coords(1.0, 2.0, 3.0);
+        let up = Vector3d::from_coords(0.0, 1.0, 0.0);
+        let target = Point3d::from_coords(1.0, 2.0, -7.0);
+        let pinhole = PerspectiveCamera::new(4, 4).look_at(eye, target, up);
+        for blades in [0, 6] {
+            let camera = PerspectiveCamera::new(4, 4)
+                .look_at(eye, target, up)
+                .aperture(0.5)
+                .focus_distance(5.0)
+                .aperture_blades(blades);
+            for pixel in [[0, 0], [2, 1], [3, 3]] {
+                let dir = pinhole.generate_ray(pixel, [0.5, 0.5]).get_direction();
+                // Where the pinhole ray crosses the plane in focus, 5 units down -Z
+                let focus_pt = eye + dir * (5.0 / -dir.z);
+
+                let mut origins_spread = 0.0f32;
+                for _ in 0..64 {
+                    let ray = camera.generate_ray(pixel, [0.5, 0.5]);
+                    let lens_offset = ray.get_origin() - eye;
+                    // On the lens, within the aperture
+                    assert!(lens_offset.z.abs() < EPSILON && lens_offset.len() <= 0.5 + EPSILON);
+                    origins_spread = origins_spread.max(lens_offset.len());
+                    // All the rays of the pixel meet on the plane in focus
+                    let t = 5.0 / -ray.get_direction().z;
+                    let hit_pt = ray.get_origin() + ray.get_direction() * t;
+                    assert!((hit_pt - focus_pt).len() < 1e-4, "{:?} != {:?}", hit_pt, focus_pt);
+                }
+                assert!(origins_spread > 0.1);
+            }
+        }
+    }
+}
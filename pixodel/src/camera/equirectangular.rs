@@ -0,0 +1,58 @@
+use std::f32::consts::PI;
+
+use geometry::{Point3d, Ray3d, Vector3d};
+
+use crate::camera::{Camera, Frame};
+
+/// 360 degree panorama, the columns of the image go around the full longitude and the rows from
+/// straight down to straight up. The viewing direction is in the middle of the image, the images
+/// are meant to have a 2:1 aspect ratio.
+#[derive(Copy, Clone, Debug)]
+pub struct EquirectangularCamera {
+    frame: Frame,
+}
+
+impl EquirectangularCamera {
+    /// At the origin looking down -Z
+    pub fn new(width: u32, height: u32) -> Self {
+        EquirectangularCamera {
+            frame: Frame::new(width, height),
+        }
+    }
+
+    /// Puts the camera at eye looking at target, up tells which way the poles are
+    pub fn look_at(mut self, eye: Point3d, target: Point3d, up: Vector3d) -> Self {
+        self.frame.look_at(eye, target, up);
+        self
+    }
+    /// Nothing closer than near or further than far from the eye is seen
+    pub fn clip(mut self, near: f32, far: f32) -> Self {
+        self.frame.near = near;
+        self.frame.far = far;
+        self
+    }
+    /// When the shutter opens and closes, 0.0 being the start transforms of the moving objects and
+    /// 1.0 the end ones
+    pub fn shutter(mut self, open: f32, close: f32) -> Self {
+        self.frame.shutter = [open, close];
+        self
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn generate_ray(&self, pixel: [u32; 2], sample_offset: [f32; 2]) -> Ray3d {
+        let (ndc_x, ndc_y) = self.frame.get_ndc(pixel, sample_offset);
+        let longitude = ndc_x * PI;
+        let latitude = (ndc_y * PI / 2.0).clamp(-PI / 2.0, PI / 2.0);
+        let dir = self.frame.get_world_vector(
+            latitude.cos() * longitude.sin(),
+            latitude.sin(),
+            latitude.cos() * longitude.cos(),
+        );
+        self.frame.clip_radial(self.frame.new_ray(self.frame.eye, dir))
+    }
+
+    fn get_pixel_spread_angle(&self) -> f32 {
+        PI / self.frame.height as f32
+    }
+}
@@ -0,0 +1,69 @@
+use std::f32::consts::PI;
+
+use geometry::{Point3d, Ray3d, Vector3d};
+
+use crate::camera::{Camera, Frame};
+
+/// Equidistant fisheye lens, the angle from the viewing direction grows linearly with the
+/// distance from the image center. Can see behind itself with a field of view over 180 degrees.
+#[derive(Copy, Clone, Debug)]
+pub struct FisheyeCamera {
+    frame: Frame,
+    // Vertical field of view in degrees
+    fov_vert: f32,
+}
+
+impl FisheyeCamera {
+    /// At the origin looking down -Z, the aspect ratio that of the image
+    pub fn new(width: u32, height: u32) -> Self {
+        FisheyeCamera {
+            frame: Frame::new(width, height),
+            fov_vert: 180.0,
+        }
+    }
+
+    /// Puts the camera at eye looking at target, up tells where the top of the image is
+    pub fn look_at(mut self, eye: Point3d, target: Point3d, up: Vector3d) -> Self {
+        self.frame.look_at(eye, target, up);
+        self
+    }
+    /// Vertical field of view in degrees, the image circle touches the top and the bottom edges
+    pub fn fov(mut self, fov_vert: f32) -> Self {
+        self.fov_vert = fov_vert;
+        self
+    }
+    pub fn aspect(mut self, aspect: f32) -> Self {
+        self.frame.aspect = aspect;
+        self
+    }
+    /// Nothing closer than near or further than far from the eye is seen
+    pub fn clip(mut self, near: f32, far: f32) -> Self {
+        self.frame.near = near;
+        self.frame.far = far;
+        self
+    }
+    /// When the shutter opens and closes, 0.0 being the start transforms of the moving objects and
+    /// 1.0 the end ones
+    pub fn shutter(mut self, open: f32, close: f32) -> Self {
+        self.frame.shutter = [open, close];
+        self
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn generate_ray(&self, pixel: [u32; 2], sample_offset: [f32; 2]) -> Ray3d {
+        let (ndc_x, ndc_y) = self.frame.get_ndc(pixel, sample_offset);
+        let ndc_x = ndc_x * self.frame.aspect;
+        // Beyond the image circle the mapping just goes on, up to straight behind the camera
+        let r = (ndc_x * ndc_x + ndc_y * ndc_y).sqrt();
+        let theta = (r * self.fov_vert.to_radians() / 2.0).min(PI);
+        let (sin_phi, cos_phi) = if r > 0.0 { (ndc_y / r, ndc_x / r) } else { (0.0, 1.0) };
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let dir = self.frame.get_world_vector(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta);
+        self.frame.clip_radial(self.frame.new_ray(self.frame.eye, dir))
+    }
+
+    fn get_pixel_spread_angle(&self) -> f32 {
+        self.fov_vert.to_radians() / self.frame.height as f32
+    }
+}
@@ -0,0 +1,64 @@
+use geometry::{Point3d, Ray3d, Vector3d};
+
+use crate::camera::{Camera, Frame};
+
+/// Parallel projection, all the rays go along the viewing direction from a rectangle around the
+/// eye. Sizes do not change with the distance, the way CAD views show the objects.
+#[derive(Copy, Clone, Debug)]
+pub struct OrthographicCamera {
+    frame: Frame,
+    // Height of the visible rectangle in world units
+    view_height: f32,
+}
+
+impl OrthographicCamera {
+    /// At the origin looking down -Z, the aspect ratio that of the image
+    pub fn new(width: u32, height: u32) -> Self {
+        OrthographicCamera {
+            frame: Frame::new(width, height),
+            view_height: 1.0,
+        }
+    }
+
+    /// Puts the camera at eye looking at target, up tells where the top of the image is
+    pub fn look_at(mut self, eye: Point3d, target: Point3d, up: Vector3d) -> Self {
+        self.frame.look_at(eye, target, up);
+        self
+    }
+    /// Height of the part of the scene seen, in world units
+    pub fn view_height(mut self, view_height: f32) -> Self {
+        self.view_height = view_height;
+        self
+    }
+    pub fn aspect(mut self, aspect: f32) -> Self {
+        self.frame.aspect = aspect;
+        self
+    }
+    /// Nothing closer than near or further than far along the viewing direction is seen
+    pub fn clip(mut self, near: f32, far: f32) -> Self {
+        self.frame.near = near;
+        self.frame.far = far;
+        self
+    }
+    /// When the shutter opens and closes, 0.0 being the start transforms of the moving objects and
+    /// 1.0 the end ones
+    pub fn shutter(mut self, open: f32, close: f32) -> Self {
+        self.frame.shutter = [open, close];
+        self
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn generate_ray(&self, pixel: [u32; 2], sample_offset: [f32; 2]) -> Ray3d {
+        let (ndc_x, ndc_y) = self.frame.get_ndc(pixel, sample_offset);
+        let half_height = self.view_height / 2.0;
+        let half_width = half_height * self.frame.aspect;
+        let offset = self.frame.get_world_vector(ndc_x * half_width, ndc_y * half_height, 0.0);
+        let origin = self.frame.eye + offset;
+        self.frame.clip_planar(self.frame.new_ray(origin, self.frame.forward))
+    }
+
+    fn get_pixel_spread_angle(&self) -> f32 {
+        0.0
+    }
+}
@@ -0,0 +1,130 @@
+use std::f32::consts::PI;
+
+use geometry::{Point3d, Ray3d, Vector3d};
+
+use crate::camera::{Camera, Frame};
+use crate::scene::random::random_f32;
+
+/// Perspective camera, a pinhole one unless given an aperture: then it is a thin lens focused at
+/// the focus distance and everything nearer or further gets blurred. With an aperture every ray
+/// starts at a random point of the lens, the depth of field converges as the samples of the pixel
+/// add up.
+#[derive(Copy, Clone, Debug)]
+pub struct PerspectiveCamera {
+    frame: Frame,
+    // Vertical field of view in degrees
+    fov_vert: f32,
+    // Radius of the lens, 0.0 for a pinhole
+    aperture_radius: f32,
+    // Distance of the plane in focus along the viewing direction
+    focus_distance: f32,
+    // Number of the aperture blades shaping the bokeh, 0 for a round one
+    aperture_blades: u32,
+}
+
+impl PerspectiveCamera {
+    /// At the origin looking down -Z, the aspect ratio that of the image
+    pub fn new(width: u32, height: u32) -> Self {
+        PerspectiveCamera {
+            frame: Frame::new(width, height),
+            fov_vert: 35.0,
+            aperture_radius: 0.0,
+            focus_distance: 1.0,
+            aperture_blades: 0,
+        }
+    }
+
+    /// Puts the camera at eye looking at target, up tells where the top of the image is
+    pub fn look_at(mut self, eye: Point3d, target: Point3d, up: Vector3d) -> Self {
+        self.frame.look_at(eye, target, up);
+        self
+    }
+    /// Vertical field of view in degrees
+    pub fn fov(mut self, fov_vert: f32) -> Self {
+        self.fov_vert = fov_vert;
+        self
+    }
+    pub fn aspect(mut self, aspect: f32) -> Self {
+        self.frame.aspect = aspect;
+        self
+    }
+    /// Nothing closer than near or further than far along the viewing direction is seen
+    pub fn clip(mut self, near: f32, far: f32) -> Self {
+        self.frame.near = near;
+        self.frame.far = far;
+        self
+    }
+    /// When the shutter opens and closes, 0.0 being the start transforms of the moving objects and
+    /// 1.0 the end ones
+    pub fn shutter(mut self, open: f32, close: f32) -> Self {
+        self.frame.shutter = [open, close];
+        self
+    }
+    /// Radius of the lens in world units, the larger the shallower the depth of field
+    pub fn aperture(mut self, aperture_radius: f32) -> Self {
+        self.aperture_radius = aperture_radius;
+        self
+    }
+    pub fn focus_distance(mut self, focus_distance: f32) -> Self {
+        self.focus_distance = focus_distance;
+        self
+    }
+    /// Makes the aperture a regular polygon, the out of focus highlights take its shape
+    pub fn aperture_blades(mut self, aperture_blades: u32) -> Self {
+        self.aperture_blades = aperture_blades;
+        self
+    }
+
+    pub fn get_eye(&self) -> Point3d {
+        self.frame.eye
+    }
+    pub fn get_forward(&self) -> Vector3d {
+        self.frame.forward
+    }
+
+    // Uniformly distributed point of the aperture, relative to its center
+    fn sample_aperture(&self) -> (f32, f32) {
+        if self.aperture_blades < 3 {
+            let r = self.aperture_radius * random_f32().sqrt();
+            let phi = 2.0 * PI * random_f32();
+            return (r * phi.cos(), r * phi.sin());
+        }
+        // The polygon is a fan of equal triangles around the center, pick one and a point in it
+        let blades = self.aperture_blades as f32;
+        let sector = (random_f32() * blades).floor().min(blades - 1.0);
+        let (a, b) = (2.0 * PI * sector / blades, 2.0 * PI * (sector + 1.0) / blades);
+        let (mut s, mut t) = (random_f32(), random_f32());
+        if s + t > 1.0 {
+            s = 1.0 - s;
+            t = 1.0 - t;
+        }
+        let x = s * a.cos() + t * b.cos();
+        let y = s * a.sin() + t * b.sin();
+        (self.aperture_radius * x, self.aperture_radius * y)
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn generate_ray(&self, pixel: [u32; 2], sample_offset: [f32; 2]) -> Ray3d {
+        // Image plane at the distance of 1, [-1; 1] maps to the field of view
+        let (ndc_x, ndc_y) = self.frame.get_ndc(pixel, sample_offset);
+        let half_height = (self.fov_vert / 2.0).to_radians().tan();
+        let half_width = half_height * self.frame.aspect;
+        let (x, y) = (ndc_x * half_width, ndc_y * half_height);
+        let dir = self.frame.get_world_vector(x, y, 1.0).normalize();
+
+        let eye = self.frame.eye;
+        if self.aperture_radius <= 0.0 {
+            return self.frame.clip_planar(self.frame.new_ray(eye, dir));
+        }
+        // All the rays through the lens meet again on the plane in focus
+        let focus_pt = eye + dir * (self.focus_distance / (dir * self.frame.forward));
+        let (lens_x, lens_y) = self.sample_aperture();
+        let lens_pt = eye + self.frame.get_world_vector(lens_x, lens_y, 0.0);
+        self.frame.clip_planar(self.frame.new_ray(lens_pt, (focus_pt - lens_pt).normalize()))
+    }
+
+    fn get_pixel_spread_angle(&self) -> f32 {
+        self.fov_vert.to_radians() / self.frame.height as f32
+    }
+}
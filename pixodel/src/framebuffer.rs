@@ -0,0 +1,321 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use image::{ImageBuffer, Rgb};
+
+use crate::scene::Color;
+
+/// How the unbounded scene radiance gets squeezed into the [0; 1] range of an 8-bit image
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ToneMapping {
+    /// Everything above 1.0 is clipped
+    Clip,
+    /// 1 - e^(-c), like the response of a photographic film
+    Exposure,
+    /// c / (1 + L), L being the luminance, keeps the hue of bright colors
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    AcesFilmic,
+}
+
+impl ToneMapping {
+    pub fn map(&self, color: Color) -> Color {
+        match self {
+            ToneMapping::Clip => color,
+            ToneMapping::Exposure => Color::new(
+                1.0 - (-color.r).exp(),
+                1.0 - (-color.g).exp(),
+                1.0 - (-color.b).exp(),
+            ),
+            ToneMapping::Reinhard => color / (1.0 + color.get_luminance()),
+            ToneMapping::AcesFilmic => {
+                let aces = |x: f32| (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+                Color::new(aces(color.r), aces(color.g), aces(color.b))
+            }
+        }
+    }
+}
+
+/// Linear, unclipped radiance of the rendered pixels. Rows go from the bottom of the image to the
+/// top, the way the primary rays are generated; the writers flip them.
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color::black(); (width * height) as usize],
+        }
+    }
+
+    pub fn get_width(&self) -> u32 {
+        self.width
+    }
+    pub fn get_height(&self) -> u32 {
+        self.height
+    }
+    pub fn get_pixel(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+    pub fn get_pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+    pub fn get_pixels_mut(&mut self) -> &mut [Color] {
+        &mut self.pixels
+    }
+
+    // Rows from the top of the image to the bottom
+    fn rows_top_down(&self) -> impl Iterator<Item = &[Color]> {
+        self.pixels.chunks(self.width as usize).rev()
+    }
+
+    /// Tone maps and sRGB encodes the image, exposure is in stops
+    pub fn to_srgb8(&self, tone_mapping: ToneMapping, exposure: f32) -> Vec<[u8; 3]> {
+        let scale = 2.0_f32.powf(exposure);
+        self.rows_top_down()
+            .flatten()
+            .map(|x| tone_mapping.map(*x * scale).to_srgb8())
+            .collect()
+    }
+
+    pub fn save_png<Q: AsRef<Path>>(
+        &self,
+        path: Q,
+        tone_mapping: ToneMapping,
+        exposure: f32,
+    ) -> image::ImageResult<()> {
+        let data = self.to_srgb8(tone_mapping, exposure).into_iter().flatten().collect();
+        let img: ImageBuffer<Rgb<u8>, Vec<u8>> =
+            ImageBuffer::from_vec(self.width, self.height, data).unwrap();
+        img.save(path)
+    }
+
+    /// Writes the linear radiance to an uncompressed single-part scanline OpenEXR file with
+    /// 32-bit float channels
+    pub fn save_exr<Q: AsRef<Path>>(&self, path: Q) -> std::io::Result<()> {
+        let (width, height) = (self.width as i32, self.height as i32);
+
+        let mut header = vec![0x76, 0x2f, 0x31, 0x01]; // magic number
+        header.extend_from_slice(&2_u32.to_le_bytes()); // version 2, scanline image
+
+        let write_attr = |header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]| {
+            header.extend_from_slice(name.as_bytes());
+            header.push(0);
+            header.extend_from_slice(kind.as_bytes());
+            header.push(0);
+            header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+            header.extend_from_slice(value);
+        };
+
+        // Channels have to be listed and stored in alphabetical order
+        let mut channels = Vec::new();
+        for name in ["B", "G", "R"] {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&2_i32.to_le_bytes()); // FLOAT
+            channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear, reserved
+            channels.extend_from_slice(&1_i32.to_le_bytes()); // x sampling
+            channels.extend_from_slice(&1_i32.to_le_bytes()); // y sampling
+        }
+        channels.push(0);
+
+        let window: Vec<u8> = [0, 0, width - 1, height - 1]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+
+        write_attr(&mut header, "channels", "chlist", &channels);
+        write_attr(&mut header, "compression", "compression", &[0]); // none
+        write_attr(&mut header, "dataWindow", "box2i", &window);
+        write_attr(&mut header, "displayWindow", "box2i", &window);
+        write_attr(&mut header, "lineOrder", "lineOrder", &[0]); // increasing y
+        write_attr(&mut header, "pixelAspectRatio", "float", &1.0_f32.to_le_bytes());
+        write_attr(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        write_attr(&mut header, "screenWindowWidth", "float", &1.0_f32.to_le_bytes());
+        header.push(0); // end of the header
+
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&header)?;
+
+        // Offset table, one chunk per scanline follows right after it
+        let chunk_len = 8 + 3 * 4 * self.width as u64;
+        let first_chunk = header.len() as u64 + 8 * self.height as u64;
+        for y in 0..self.height as u64 {
+            out.write_all(&(first_chunk + y * chunk_len).to_le_bytes())?;
+        }
+
+        for (y, row) in self.rows_top_down().enumerate() {
+            out.write_all(&(y as i32).to_le_bytes())?;
+            out.write_all(&((chunk_len - 8) as i32).to_le_bytes())?;
+            for channel in [|c: &Color| c.b, |c: &Color| c.g, |c: &Color| c.r] {
+                for pixel in row {
+                    out.write_all(&channel(pixel).to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+
+    /// Writes the linear radiance to a run-length encoded Radiance RGBE file
+    pub fn save_hdr<Q: AsRef<Path>>(&self, path: Q) -> std::io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", self.height, self.width)?;
+
+        // Readers only accept run-length encoding for these widths
+        let rle = (8..0x8000).contains(&self.width);
+        let mut components: [Vec<u8>; 4] = Default::default();
+
+        for row in self.rows_top_down() {
+            let rgbe: Vec<[u8; 4]> = row.iter().map(|x| to_rgbe(*x)).collect();
+            if !rle {
+                out.write_all(&rgbe.concat())?;
+                continue;
+            }
+
+            out.write_all(&[2, 2, (self.width >> 8) as u8, self.width as u8])?;
+            for (i, component) in components.iter_mut().enumerate() {
+                component.clear();
+                component.extend(rgbe.iter().map(|x| x[i]));
+            }
+            // Every component is stored separately, as literal runs of up to 128 bytes
+            for component in components.iter() {
+                for run in component.chunks(128) {
+                    out.write_all(&[run.len() as u8])?;
+                    out.write_all(run)?;
+                }
+            }
+        }
+        out.flush()
+    }
+}
+
+// Shared exponent encoding, the mantissas of all the components are relative to the largest one
+fn to_rgbe(color: Color) -> [u8; 4] {
+    let max = color.r.max(color.g).max(color.b);
+    if max < 1e-32 {
+        return [0; 4];
+    }
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2.0_f32.powi(exponent);
+    let mantissa = |c: f32| (c.max(0.0) * scale).min(255.0) as u8;
+    [
+        mantissa(color.r),
+        mantissa(color.g),
+        mantissa(color.b),
+        (exponent + 128) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+    use std::path::PathBuf;
+
+    // Wide enough for the run-length encoding of the Radiance files, with values way above 1.0
+    fn get_test_framebuffer() -> Framebuffer {
+        let mut fb = Framebuffer::new(9, 2);
+        for (i, pixel) in fb.get_pixels_mut().iter_mut().enumerate() {
+            let x = i as f32;
+            *pixel = Color::new(0.1 * x, 2.0 + x, 1000.0 / (1.0 + x));
+        }
+        fb
+    }
+
+    fn get_temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pixodel_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn t_exr_round_trip() {
+        let fb = get_test_framebuffer();
+        let path = get_temp_path("t_exr_round_trip.exr");
+        fb.save_exr(&path).unwrap();
+        let img = image::open(&path).unwrap().into_rgb32f();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(img.dimensions(), (fb.get_width(), fb.get_height()));
+        for y in 0..fb.get_height() {
+            for x in 0..fb.get_width() {
+                // The files are stored top row first
+                let [r, g, b] = img.get_pixel(x, fb.get_height() - 1 - y).0;
+                assert_eq!(Color::new(r, g, b), fb.get_pixel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn t_hdr_round_trip() {
+        let fb = get_test_framebuffer();
+        let path = get_temp_path("t_hdr_round_trip.hdr");
+        fb.save_hdr(&path).unwrap();
+        let file = BufReader::new(File::open(&path).unwrap());
+        let decoder = image::codecs::hdr::HdrDecoder::new(file).unwrap();
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((metadata.width, metadata.height), (fb.get_width(), fb.get_height()));
+        for y in 0..fb.get_height() {
+            for x in 0..fb.get_width() {
+                let [r, g, b] = pixels[((fb.get_height() - 1 - y) * fb.get_width() + x) as usize].0;
+                let expected = fb.get_pixel(x, y);
+                // 8-bit mantissas relative to the largest component
+                let tolerance = expected.r.max(expected.g).max(expected.b) / 128.0;
+                assert!((r - expected.r).abs() <= tolerance, "{} != {}", r, expected.r);
+                assert!((g - expected.g).abs() <= tolerance, "{} != {}", g, expected.g);
+                assert!((b - expected.b).abs() <= tolerance, "{} != {}", b, expected.b);
+            }
+        }
+    }
+
+    #[test]
+    fn t_tone_mapping() {
+        let operators = [
+            ToneMapping::Clip,
+            ToneMapping::Exposure,
+            ToneMapping::Reinhard,
+            ToneMapping::AcesFilmic,
+        ];
+        for tone_mapping in operators {
+            assert_eq!(tone_mapping.map(Color::black()), Color::black());
+            // Brighter in, brighter out
+            let mut prev = 0.0;
+            for i in 1..100 {
+                let mapped = tone_mapping.map(Color::grey(i as f32 * 0.1)).r;
+                assert!(mapped > prev, "{:?} is not increasing", tone_mapping);
+                prev = mapped;
+            }
+        }
+
+        assert_eq!(ToneMapping::Clip.map(Color::grey(5.0)), Color::grey(5.0));
+        // Everything but clipping keeps the highlights within the displayable range
+        for tone_mapping in [ToneMapping::Exposure, ToneMapping::Reinhard] {
+            assert!(tone_mapping.map(Color::grey(1000.0)).r <= 1.0);
+        }
+        // The ACES fit levels off slightly above 1.0
+        assert!((ToneMapping::AcesFilmic.map(Color::grey(1000.0)).r - 2.51 / 2.43).abs() < 0.01);
+        let expected = 1.0 - (-1.0_f32).exp();
+        assert!((ToneMapping::Exposure.map(Color::grey(1.0)).r - expected).abs() < 1e-6);
+        assert_eq!(ToneMapping::Reinhard.map(Color::grey(1.0)), Color::grey(0.5));
+        // Reinhard scales all the channels alike, the hue stays
+        let mapped = ToneMapping::Reinhard.map(Color::new(4.0, 2.0, 1.0));
+        assert!((mapped.r / mapped.g - 2.0).abs() < 1e-6);
+        assert!((mapped.g / mapped.b - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn t_exposure() {
+        let mut fb = Framebuffer::new(1, 1);
+        fb.get_pixels_mut()[0] = Color::grey(0.25);
+        let brighter = fb.to_srgb8(ToneMapping::Clip, 1.0);
+        fb.get_pixels_mut()[0] = Color::grey(0.5);
+        assert_eq!(brighter, fb.to_srgb8(ToneMapping::Clip, 0.0));
+    }
+}
@@ -7,4 +7,8 @@ use std::sync::Arc;
 use std::time::Instant;
 
 
+pub mod antialiasing;
+pub mod camera;
+pub mod framebuffer;
 pub mod scene;
+pub mod scene_file;
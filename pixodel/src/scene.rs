@@ -3,31 +3,59 @@
 //use mesh::Mesh;
 
 use geometry::ray::Ray3d;
-use geometry::{Mat4f, Point3d, Point4d, PrimitiveType, TraceablePrimitive, Vector3d};
+use geometry::{HitRecord, Mat4f, Motion, Point3d, Point4d, PrimitiveType, TraceablePrimitive, Vector3d};
 use geometry::aabb::Aabb;
 use geometry::triangle::Triangle;
-pub use crate::scene::light::Light;
+pub use crate::scene::color::Color;
+pub use crate::scene::light::{Light, LightType};
+pub use crate::scene::material::Material;
+pub use crate::scene::path_tracer::PathTracer;
+pub use crate::scene::shading::Shader;
+pub use crate::scene::texture::{FilterMode, Texture, WrapMode};
 pub use crate::scene::sphere::SphereObj;
 pub use crate::scene::triangle::TriObj;
 pub use crate::scene::wfobj::WfObj;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 use lbvh::*;
 use wavefront_obj::obj::Primitive;
 //use crate::traceable::PrimitiveType;
 
+pub mod color;
 pub mod light;
+pub mod material;
+pub mod microfacet;
+pub mod path_tracer;
+pub mod random;
 pub mod triangle;
 pub mod wfobj;
 //pub mod tracing;
 pub mod shading;
+pub mod texture;
 mod sphere;
 
+const LBVH_LEAF_CAPACITY: usize = 8;
+
+// Secondary rays start this far off the surface so that they do not hit it again right away
+const SECONDARY_RAY_OFFSET: f32 = 0.001;
+
+/// Acceleration structure over all the primitives of a [`Scene`]
+pub type SceneLbvh = OwnedOctree<PrimitiveType, LBVH_LEAF_CAPACITY>;
+
 pub struct Scene {
     pub lights: Vec<Light>,
     pub objects: Vec<SceneObj>,
     primitives: Vec<PrimitiveType>,
+    // Index of the object each primitive comes from
+    primitive_to_obj: Vec<usize>,
+    // Built on first use, dropped whenever the primitive list changes
+    lbvh: OnceLock<SceneLbvh>,
+    lbvh_builder: OctreeBuilder,
+    // Seen where rays escape the scene, lights the scene from all directions when path tracing
+    background: Color,
+    // Angle between the primary rays of neighbouring pixels, sizes the texture footprints
+    pixel_spread_angle: f32,
 }
 
 type IndexedCentroid = (usize, Point3d);
@@ -37,25 +65,80 @@ fn reflection_dir(surface_normal: Vector3d, surface_to_camera: Vector3d) -> Vect
     surface_normal * l2n_cos * 2.0 - surface_to_camera
 }
 
+// Snell's law; eta is the ratio of the refraction indices of the media the ray goes from and to,
+// the normal faces the incident ray. Returns None in case of total internal reflection.
+fn refraction_dir(surface_normal: Vector3d, incident_dir: Vector3d, eta: f32) -> Option<Vector3d> {
+    let cos_i = -(incident_dir * surface_normal);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+    if k < 0.0 {
+        None
+    } else {
+        Some(incident_dir * eta + surface_normal * (eta * cos_i - k.sqrt()))
+    }
+}
+
+// Share of the light that gets reflected off a dielectric surface, according to the Fresnel
+// equations for unpolarized light
+fn fresnel(surface_normal: Vector3d, incident_dir: Vector3d, eta: f32) -> f32 {
+    let cos_i = -(incident_dir * surface_normal);
+    let sin_t_squared = eta * eta * (1.0 - cos_i * cos_i);
+    if sin_t_squared >= 1.0 {
+        return 1.0; // total internal reflection
+    }
+    let cos_t = (1.0 - sin_t_squared).sqrt();
+    let r_parallel = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let r_perpendicular = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) * 0.5
+}
+
 impl Scene {
     pub fn new() -> Self {
         Scene {
             lights: Vec::new(),
             objects: Vec::new(),
             primitives: Vec::new(),
+            primitive_to_obj: Vec::new(),
+            lbvh: OnceLock::new(),
+            lbvh_builder: OctreeBuilder::new(),
+            background: Color::from_srgb8([30, 30, 30]),
+            pixel_spread_angle: 0.0,
         }
     }
     pub fn add_obj(mut self, obj: SceneObj) -> Self {
         let model_mtx = obj.get_model_mtx();
+        let end_model_mtx = obj.get_end_model_mtx();
+        let obj_idx = self.objects.len();
         obj.object.to_primitives().into_iter().for_each(|prim| {
-            self.primitives.push(prim.model_to_world(&model_mtx));
+            let start = prim.model_to_world(&model_mtx);
+            self.primitives.push(match &end_model_mtx {
+                Some(end_mtx) => PrimitiveType::moving(start, prim.model_to_world(end_mtx)),
+                None => start,
+            });
+            self.primitive_to_obj.push(obj_idx);
         });
+        self.objects.push(obj);
+        self.lbvh = OnceLock::new();
         self
     }
     pub fn add_light(mut self, light: Light) -> Self {
         self.lights.push(light);
         self
     }
+    pub fn background(mut self, background: Color) -> Self {
+        self.background = background;
+        self
+    }
+    /// Angle in radians between the primary rays of neighbouring pixels, lets the textures pick
+    /// the mip levels matching the screen resolution
+    pub fn pixel_spread_angle(mut self, pixel_spread_angle: f32) -> Self {
+        self.pixel_spread_angle = pixel_spread_angle;
+        self
+    }
+    pub fn lbvh_builder(mut self, builder: OctreeBuilder) -> Self {
+        self.lbvh_builder = builder;
+        self.lbvh = OnceLock::new();
+        self
+    }
 
     pub fn build_lbvh<const N: usize>(&self) -> Octree<PrimitiveType, N>{
         lbvh::Octree::<PrimitiveType, N>::new(&self.primitives)
@@ -64,40 +147,135 @@ impl Scene {
     pub fn build_par_lbvh<const N: usize>(&self) -> ParOctree<PrimitiveType, N>{
         lbvh::ParOctree::<PrimitiveType, N>::new(&self.primitives)
     }
+
+    pub fn get_lbvh(&self) -> &SceneLbvh {
+        self.lbvh
+            .get_or_init(|| self.lbvh_builder.build::<PrimitiveType, LBVH_LEAF_CAPACITY>(&self.primitives))
+    }
+
+    pub fn get_background(&self) -> Color {
+        self.background
+    }
+
+    /// Material of the object the hit primitive belongs to
+    pub fn get_material(&self, hit: &HitRecord) -> &Material {
+        &self.objects[self.primitive_to_obj[hit.primitive_id]].material
+    }
+
+    /// Material at the hit point with its textures sampled
+    pub fn get_material_at(&self, hit: &HitRecord) -> Material {
+        self.get_material(hit).get_textured(hit.tex_coords, self.get_tex_footprint(hit))
+    }
+
+    /// Shading normal at the hit with the normal or bump texture of the material applied, facing
+    /// the side the ray comes from
+    pub fn get_surface_normal(&self, hit: &HitRecord) -> Vector3d {
+        let normal = match hit.tangent {
+            Some(tangent) => self.get_material(hit).get_mapped_normal(
+                hit.shading_normal,
+                tangent,
+                hit.tex_coords,
+                self.get_tex_footprint(hit),
+            ),
+            None => hit.shading_normal,
+        };
+        if hit.front_face {
+            normal
+        } else {
+            -normal
+        }
+    }
+
+    // Size of the area a pixel covers at the hit point in texture coordinates, treating the ray as
+    // a cone widening by the pixel spread angle
+    fn get_tex_footprint(&self, hit: &HitRecord) -> f32 {
+        match &self.primitives[hit.primitive_id] {
+            PrimitiveType::Triangle(t)
+            | PrimitiveType::MovingTriangle(Motion { start: t, .. }) => t
+                .get_tex_density()
+                .map_or(0.0, |density| density * hit.distance * self.pixel_spread_angle),
+            _ => 0.0,
+        }
+    }
     
-    pub fn cast_ray_lbvh<F, const N: usize>(&self, lbvh: &ParOctree<PrimitiveType, N>, ray: &Ray3d, vtx_shader: &F, depth: usize) -> [u8; 3]
-        where
-            F: FnOnce(Point3d, Point3d, Vector3d, &Vec<Light>) -> f32 + Send + Copy + 'static,
+    /// depth is the maximum number of reflections traced after the primary ray
+    pub fn cast_ray_lbvh<S>(&self, ray: &Ray3d, shader: &S, depth: usize) -> Color
+    where
+        S: Shader + ?Sized,
     {
-        const BG_COLOR: [u8; 3] = [30u8; 3];
-        const DEPTH_THRESHOLD: usize = 0;
-        
-        if depth < DEPTH_THRESHOLD {
-            return BG_COLOR;
-        }
-        
-        let nearest: Option<(usize, f32)> = lbvh.traverse(ray);
+        self.trace_ray_lbvh(ray, shader, depth)
+    }
+
+    fn trace_ray_lbvh<S>(&self, ray: &Ray3d, shader: &S, depth: usize) -> Color
+    where
+        S: Shader + ?Sized,
+    {
+        let lbvh = self.get_lbvh();
+        let nearest: Option<HitRecord> = lbvh.traverse(ray);
         
         if nearest != None {
-            let (nearest_obj, dist) = nearest.unwrap();
-            let surface_pt = *ray * dist;
-            let surface_normal: Vector3d = self.primitives[nearest_obj].get_normal(&surface_pt);
-            
-            let refl_dir = reflection_dir(surface_normal, -ray.get_direction()).normalize(); //TODO: normalize really needed?
-            
-            //let refl_color = self.cast_ray(&surface_pt, &refl_dir, vtx_shader, depth + 1);
-            
+            let hit = nearest.unwrap();
+            let surface_pt = hit.point;
+            let surface_normal = self.get_surface_normal(&hit);
+
+            let material = &self.get_material_at(&hit);
+
+            if let (Some(refraction_index), true) = (material.refraction_index, depth > 0) {
+                return self.trace_dielectric(ray, &hit, refraction_index, shader, depth);
+            }
+
             let mut illumination =
-                vtx_shader(surface_pt, ray.get_origin(), surface_normal, &self.lights);
-            if illumination > 1.0 {
-                illumination = 1.0
+                shader.shade(surface_pt, ray, surface_normal, material, &self.lights, lbvh);
+
+            let reflectivity = material.reflectivity;
+            if depth > 0 && reflectivity > 0.0 {
+                let refl_dir = reflection_dir(surface_normal, -ray.get_direction()).normalize(); //TODO: normalize really needed?
+                let refl_ray = Ray3d::from(surface_pt + surface_normal * SECONDARY_RAY_OFFSET, refl_dir)
+                    .with_time(hit.time);
+                let refl_illumination = self.trace_ray_lbvh(&refl_ray, shader, depth - 1);
+                illumination = illumination * (1.0 - reflectivity) + refl_illumination * reflectivity;
             }
-            let self_color = [(illumination * u8::MAX as f32) as u8; 3];
-            //[refl_color[0] + self_color[0]; 3]
-            [self_color[0]; 3]
-            //[100,100,100]
+            illumination
         } else {
-            BG_COLOR
+            self.background
+        }
+    }
+
+    // Transparent objects have no color of their own, the light is either reflected or refracted
+    // in the proportion given by the Fresnel equations
+    fn trace_dielectric<S>(
+        &self,
+        ray: &Ray3d,
+        hit: &HitRecord,
+        refraction_index: f32,
+        shader: &S,
+        depth: usize,
+    ) -> Color
+    where
+        S: Shader + ?Sized,
+    {
+        // Entering the object from the outside or leaving it
+        let (surface_normal, eta) = if hit.front_face {
+            (hit.shading_normal, 1.0 / refraction_index)
+        } else {
+            (-hit.shading_normal, refraction_index)
+        };
+        let incident_dir = ray.get_direction().normalize();
+
+        let refl_dir = reflection_dir(surface_normal, -incident_dir).normalize();
+        let refl_ray = Ray3d::from(hit.point + surface_normal * SECONDARY_RAY_OFFSET, refl_dir)
+            .with_time(hit.time);
+        let refl_illumination = self.trace_ray_lbvh(&refl_ray, shader, depth - 1);
+
+        match refraction_dir(surface_normal, incident_dir, eta) {
+            None => refl_illumination,
+            Some(refr_dir) => {
+                let refr_origin = hit.point - surface_normal * SECONDARY_RAY_OFFSET;
+                let refr_ray = Ray3d::from(refr_origin, refr_dir.normalize()).with_time(hit.time);
+                let refr_illumination = self.trace_ray_lbvh(&refr_ray, shader, depth - 1);
+                let kr = fresnel(surface_normal, incident_dir, eta);
+                refl_illumination * kr + refr_illumination * (1.0 - kr)
+            }
         }
     }
 }
@@ -110,6 +288,11 @@ pub struct SceneObj {
     scale: [f32; 3],
     rotation: [f32; 3],
     translation: [f32; 3],
+    // Transform at the end of the shutter interval, the parts left out stay as at the start
+    end_scale: Option<[f32; 3]>,
+    end_rotation: Option<[f32; 3]>,
+    end_translation: Option<[f32; 3]>,
+    material: Material,
 }
 
 impl SceneObj {
@@ -119,16 +302,27 @@ impl SceneObj {
             scale: [0.0, 0.0, 0.0],
             rotation: [0.0, 0.0, 0.0],
             translation: [0.0, 0.0, 0.0],
+            end_scale: None,
+            end_rotation: None,
+            end_translation: None,
+            material: Material::new(),
         }
     }
 
     fn get_model_mtx(&self) -> Mat4f {
-        Mat4f::identity()
-            .translate_xyz(&self.translation)
-            .rotate_about_x(self.rotation[0])
-            .rotate_about_y(self.rotation[1])
-            .rotate_about_z(self.rotation[2])
-            .scale_xyz(&self.scale)
+        get_model_mtx(&self.scale, &self.rotation, &self.translation)
+    }
+
+    // None for objects staying in place
+    fn get_end_model_mtx(&self) -> Option<Mat4f> {
+        if self.end_scale.is_none() && self.end_rotation.is_none() && self.end_translation.is_none() {
+            return None;
+        }
+        Some(get_model_mtx(
+            &self.end_scale.unwrap_or(self.scale),
+            &self.end_rotation.unwrap_or(self.rotation),
+            &self.end_translation.unwrap_or(self.translation),
+        ))
     }
 
     pub fn rotate(mut self, x: f32, y: f32, z: f32) -> Self {
@@ -143,5 +337,198 @@ impl SceneObj {
         self.translation = [x, y, z];
         self
     }
+    /// Scale at the end of the shutter interval, the object is motion blurred in between
+    pub fn end_scale(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.end_scale = Some([x, y, z]);
+        self
+    }
+    /// Rotation at the end of the shutter interval. The vertices move along straight lines, so
+    /// large rotations are better split over several frames.
+    pub fn end_rotate(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.end_rotation = Some([x, y, z]);
+        self
+    }
+    /// Translation at the end of the shutter interval
+    pub fn end_translate(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.end_translation = Some([x, y, z]);
+        self
+    }
+    pub fn material(mut self, material: Material) -> Self {
+        self.material = material;
+        self
+    }
+}
+
+fn get_model_mtx(scale: &[f32; 3], rotation: &[f32; 3], translation: &[f32; 3]) -> Mat4f {
+    Mat4f::identity()
+        .translate_xyz(translation)
+        .rotate_about_x(rotation[0])
+        .rotate_about_y(rotation[1])
+        .rotate_about_z(rotation[2])
+        .scale_xyz(scale)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::sphere::Sphere;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn get_vector(x: f32, y: f32, z: f32) -> Vector3d {
+        Vector3d::from_coords(x, y, z).normalize()
+    }
+
+    fn assert_vector(actual: Vector3d, expected: Vector3d) {
+        assert!((actual - expected).len() < EPSILON, "{:?} != {:?}", actual, expected);
+    }
+
+    fn get_sphere(center: Point3d, radius: f32, material: Material) -> SceneObj {
+        SceneObj::new(Arc::new(SphereObj::new(Sphere::new(center, radius))))
+            .scale(1.0, 1.0, 1.0)
+            .material(material)
+    }
+
+    // Shows the albedo of whatever the ray ends up at
+    fn albedo_shader(
+        _: Point3d,
+        _: &Ray3d,
+        _: Vector3d,
+        material: &Material,
+        _: &[Light],
+        _: &SceneLbvh,
+    ) -> Color {
+        material.albedo
+    }
+
+    #[test]
+    fn t_reflection_dir() {
+        let normal = Vector3d::from_coords(0.0, 1.0, 0.0);
+        let reflected = reflection_dir(normal, get_vector(1.0, 1.0, 0.0));
+        assert_vector(reflected, get_vector(-1.0, 1.0, 0.0));
+        assert_vector(reflection_dir(normal, normal), normal);
+        // The angle to the normal is kept for any direction
+        let surface_to_camera = get_vector(0.3, 0.5, -0.8);
+        let reflected = reflection_dir(normal, surface_to_camera);
+        assert!((reflected * normal - surface_to_camera * normal).abs() < EPSILON);
+        assert!((reflected.len() - 1.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn t_recursive_reflection() {
+        // Mirror floor under a red sphere, looking down at the floor at 45 degrees
+        let floor = Triangle::new(
+            Point3d::from_coords(-100.0, -1.0, 100.0),
+            Point3d::from_coords(100.0, -1.0, 100.0),
+            Point3d::from_coords(0.0, -1.0, -100.0),
+        );
+        let red = Color::new(1.0, 0.0, 0.0);
+        let get_scene = |reflectivity| {
+            let mirror = Material::new().albedo(Color::grey(0.5)).reflectivity(reflectivity);
+            let sphere_center = Point3d::from_coords(0.0, 1.0, -3.0);
+            Scene::new()
+                .background(Color::black())
+                .add_obj(
+                    SceneObj::new(Arc::new(TriObj::new(floor)))
+                        .scale(1.0, 1.0, 1.0)
+                        .material(mirror),
+                )
+                .add_obj(get_sphere(sphere_center, 0.5, Material::new().albedo(red)))
+        };
+        let ray = Ray3d::from(Point3d::from_coords(0.0, 0.0, 0.0), get_vector(0.0, -1.0, -1.0));
+
+        let scene = get_scene(1.0);
+        assert_eq!(scene.cast_ray_lbvh(&ray, &albedo_shader, 1), red);
+        // Out of reflections the mirror shows its own color
+        assert_eq!(scene.cast_ray_lbvh(&ray, &albedo_shader, 0), Color::grey(0.5));
+        // Partial mirrors blend the two
+        let scene = get_scene(0.25);
+        let color = scene.cast_ray_lbvh(&ray, &albedo_shader, 4);
+        assert!((color.r - (0.75 * 0.5 + 0.25)).abs() < EPSILON);
+        assert!((color.g - 0.75 * 0.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn t_refraction_dir() {
+        let normal = Vector3d::from_coords(0.0, 1.0, 0.0);
+        // Head on the ray goes straight through
+        let incident_dir = Vector3d::from_coords(0.0, -1.0, 0.0);
+        assert_vector(refraction_dir(normal, incident_dir, 1.0 / 1.5).unwrap(), incident_dir);
+
+        // Snell's law at 45 degrees into glass: sin t = sin 45 / 1.5
+        let incident_dir = get_vector(1.0, -1.0, 0.0);
+        let refracted = refraction_dir(normal, incident_dir, 1.0 / 1.5).unwrap();
+        let sin_t = std::f32::consts::FRAC_1_SQRT_2 / 1.5;
+        assert_vector(refracted, Vector3d::from_coords(sin_t, -(1.0 - sin_t * sin_t).sqrt(), 0.0));
+
+        // Leaving the glass past the critical angle of 41.8 degrees
+        let critical = (1.0_f32 / 1.5).asin().to_degrees();
+        for (angle, reflected) in [(critical - 1.0, false), (critical + 1.0, true), (60.0, true)] {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            let incident_dir = Vector3d::from_coords(sin, -cos, 0.0);
+            assert_eq!(refraction_dir(normal, incident_dir, 1.5).is_none(), reflected, "{}", angle);
+        }
+    }
+
+    #[test]
+    fn t_fresnel() {
+        let normal = Vector3d::from_coords(0.0, 1.0, 0.0);
+        let head_on = Vector3d::from_coords(0.0, -1.0, 0.0);
+        // ((n1 - n2) / (n1 + n2))^2 at normal incidence, the same both ways
+        let expected = (0.5_f32 / 2.5).powi(2);
+        assert!((fresnel(normal, head_on, 1.0 / 1.5) - expected).abs() < EPSILON);
+        assert!((fresnel(normal, head_on, 1.5) - expected).abs() < EPSILON);
+        assert!(fresnel(normal, head_on, 1.0).abs() < EPSILON);
+
+        // More reflective towards grazing angles
+        let mut previous = 0.0;
+        for angle in [0.0, 30.0, 60.0, 80.0, 89.0] {
+            let (sin, cos) = (angle as f32).to_radians().sin_cos();
+            let kr = fresnel(normal, Vector3d::from_coords(sin, -cos, 0.0), 1.0 / 1.5);
+            assert!(kr >= previous && kr < 1.0, "{} at {}", kr, angle);
+            previous = kr;
+        }
+        // Total internal reflection
+        let (sin, cos) = 50.0_f32.to_radians().sin_cos();
+        assert_eq!(fresnel(normal, Vector3d::from_coords(sin, -cos, 0.0), 1.5), 1.0);
+    }
+
+    #[test]
+    fn t_glass_sphere() {
+        // Glass ball lens in front of a wall, the camera sees the wall through it upside down
+        let wall = Triangle::new(
+            Point3d::from_coords(-100.0, -100.0, -20.0),
+            Point3d::from_coords(100.0, -100.0, -20.0),
+            Point3d::from_coords(0.0, 100.0, -20.0),
+        );
+        let glass = Material::new().dielectric(1.5);
+        let scene = Scene::new()
+            .background(Color::black())
+            .add_obj(SceneObj::new(Arc::new(TriObj::new(wall))).scale(1.0, 1.0, 1.0))
+            .add_obj(get_sphere(Point3d::from_coords(0.0, 0.0, -5.0), 1.0, glass));
+
+        // Where the refracted rays reaching the wall left the sphere
+        let wall_hits = std::sync::Mutex::new(Vec::new());
+        let shader =
+            |surface_pt: Point3d, ray: &Ray3d, _: Vector3d, _: &Material, _: &[Light], _: &_| {
+                wall_hits.lock().unwrap().push((surface_pt, ray.get_origin()));
+                Color::white()
+            };
+        let ray = Ray3d::from(Point3d::from_coords(0.0, 0.3, 0.0), get_vector(0.0, 0.0, -1.0));
+        let color = scene.cast_ray_lbvh(&ray, &shader, 2);
+        // Mostly refracted at both the surfaces
+        assert!(color.g > 0.8 && color.g < 1.0, "{:?}", color);
+
+        // The rest got reflected inside the sphere, out of depth for refracting again
+        let wall_hits: Vec<_> =
+            wall_hits.into_inner().unwrap().into_iter().filter(|x| x.0.z < -19.0).collect();
+        assert_eq!(wall_hits.len(), 1);
+        let (wall_pt, exit_pt) = wall_hits[0];
+        assert!(((exit_pt - Point3d::from_coords(0.0, 0.0, -5.0)).len() - 1.0).abs() < 0.01);
+        assert!(exit_pt.z < -5.0, "{:?}", exit_pt);
+        // Bent towards the axis and across it
+        assert!(exit_pt.y < 0.3);
+        assert!(wall_pt.y < 0.0, "{:?}", wall_pt);
+    }
+}
@@ -0,0 +1,166 @@
+/// Linear light RGB, not limited to [0; 1] until it gets written out
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+// sRGB transfer functions, see IEC 61966-2-1
+fn srgb_encode(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn srgb_decode(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl Color {
+    pub fn new(r: f32, g: f32, b: f32) -> Self {
+        Color { r, g, b }
+    }
+    pub fn grey(value: f32) -> Self {
+        Color::new(value, value, value)
+    }
+    pub fn black() -> Self {
+        Color::grey(0.0)
+    }
+    pub fn white() -> Self {
+        Color::grey(1.0)
+    }
+
+    /// Converts a color as stored in images and color pickers to the linear space
+    pub fn from_srgb8(rgb: [u8; 3]) -> Self {
+        let decode = |c: u8| srgb_decode(c as f32 / u8::MAX as f32);
+        Color::new(decode(rgb[0]), decode(rgb[1]), decode(rgb[2]))
+    }
+
+    /// Quantizes the color for the framebuffer, anything outside of [0; 1] gets clipped
+    pub fn to_srgb8(&self) -> [u8; 3] {
+        let encode = |c: f32| (srgb_encode(c.clamp(0.0, 1.0)) * u8::MAX as f32).round() as u8;
+        [encode(self.r), encode(self.g), encode(self.b)]
+    }
+
+    pub fn get_luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+}
+
+impl core::ops::Add<Color> for Color {
+    type Output = Self;
+
+    fn add(self, other: Color) -> Self::Output {
+        Self::Output {
+            r: self.r + other.r,
+            g: self.g + other.g,
+            b: self.b + other.b,
+        }
+    }
+}
+
+impl core::ops::AddAssign<Color> for Color {
+    fn add_assign(&mut self, other: Color) {
+        *self = *self + other;
+    }
+}
+
+impl core::ops::Sub<Color> for Color {
+    type Output = Self;
+
+    fn sub(self, other: Color) -> Self::Output {
+        Self::Output {
+            r: self.r - other.r,
+            g: self.g - other.g,
+            b: self.b - other.b,
+        }
+    }
+}
+
+// Component-wise, e.g. light filtered by a surface
+impl core::ops::Mul<Color> for Color {
+    type Output = Self;
+
+    fn mul(self, other: Color) -> Self::Output {
+        Self::Output {
+            r: self.r * other.r,
+            g: self.g * other.g,
+            b: self.b * other.b,
+        }
+    }
+}
+
+impl core::ops::Mul<f32> for Color {
+    type Output = Self;
+
+    fn mul(self, other: f32) -> Self::Output {
+        Self::Output {
+            r: self.r * other,
+            g: self.g * other,
+            b: self.b * other,
+        }
+    }
+}
+
+impl core::ops::Div<f32> for Color {
+    type Output = Self;
+
+    fn div(self, other: f32) -> Self::Output {
+        Self::Output {
+            r: self.r / other,
+            g: self.g / other,
+            b: self.b / other,
+        }
+    }
+}
+
+impl std::iter::Sum for Color {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Color::black(), |acc, x| acc + x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_srgb_known_points() {
+        assert_eq!(Color::black().to_srgb8(), [0, 0, 0]);
+        assert_eq!(Color::white().to_srgb8(), [255, 255, 255]);
+        assert_eq!(Color::grey(0.5).to_srgb8(), [188; 3]);
+        let color = Color::from_srgb8([0, 255, 188]);
+        assert_eq!((color.r, color.g), (0.0, 1.0));
+        assert!((color.b - 0.5).abs() < 0.005);
+        // Out of range radiance gets clipped
+        assert_eq!(Color::new(-1.0, 2.0, 1000.0).to_srgb8(), [0, 255, 255]);
+    }
+
+    #[test]
+    fn t_srgb_knee() {
+        // The linear segment and the power curve meet without a jump in either direction
+        let knee = 0.0031308;
+        assert!((srgb_encode(knee) - 0.04045).abs() < 1e-5);
+        assert!((srgb_encode(knee + 1e-6) - srgb_encode(knee)).abs() < 1e-4);
+        assert!((srgb_decode(0.04045) - knee).abs() < 1e-6);
+        assert!((srgb_decode(0.04045 + 1e-6) - srgb_decode(0.04045)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn t_srgb_round_trip() {
+        for value in 0..=u8::MAX {
+            assert_eq!(Color::from_srgb8([value; 3]).to_srgb8(), [value; 3]);
+        }
+        for i in 0..=100 {
+            let linear = i as f32 / 100.0;
+            assert!((srgb_decode(srgb_encode(linear)) - linear).abs() < 1e-5);
+        }
+    }
+}
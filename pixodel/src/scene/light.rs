@@ -1,18 +1,320 @@
-//pub mod light {
-use geometry::Point3d;
+use std::f32::consts::PI;
+
+use geometry::{Point3d, Vector3d};
+use crate::scene::color::Color;
+use crate::scene::random::{get_orthonormal_basis, random_f32, random_unit_vector};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LightType {
+    /// Shines equally in all directions
+    Point { position: Point3d },
+    /// Infinitely far away like the sun, all the rays are parallel and not attenuated
+    Directional { direction: Vector3d },
+    /// Point light limited to a cone, full intensity within cos_inner, fading out to cos_outer
+    Spot {
+        position: Point3d,
+        direction: Vector3d,
+        cos_inner: f32,
+        cos_outer: f32,
+    },
+    /// Two-sided emitting parallelogram spanned by the edges
+    Rectangle {
+        corner: Point3d,
+        edge_u: Vector3d,
+        edge_v: Vector3d,
+    },
+    Sphere { center: Point3d, radius: f32 },
+}
 
 #[derive(Copy, Clone)]
 pub struct Light {
-    pub position: Point3d,
+    pub kind: LightType,
     pub intensity: f32,
+    pub color: Color,
+    // Points sampled on area lights for every shaded point, more give smoother soft shadows
+    pub num_samples: usize,
+}
+
+/// Light reaching a surface point from a single point of a light
+pub struct LightSample {
+    // Unit vector
+    pub surface_to_light: Vector3d,
+    // f32::MAX for directional lights
+    pub distance: f32,
+    // Attenuated by the distance, the spot cone and the emitting surface orientation
+    pub radiance: Color,
 }
 
 impl Light {
-    pub fn new(position: Point3d, intensity: f32) -> Light {
-        Light {
+    /// Point light, fading with the square of the distance. intensity is the radiance at the
+    /// distance of 1.
+    pub fn point(position: Point3d, intensity: f32) -> Light {
+        Light::from_type(LightType::Point { position }, intensity)
+    }
+    /// The direction is the one the light travels in
+    pub fn directional(direction: Vector3d, intensity: f32) -> Light {
+        let direction = direction.normalize();
+        Light::from_type(LightType::Directional { direction }, intensity)
+    }
+    /// The cone angles are in degrees, measured from the direction to the cone surface
+    pub fn spot(
+        position: Point3d,
+        direction: Vector3d,
+        inner_angle: f32,
+        outer_angle: f32,
+        intensity: f32,
+    ) -> Light {
+        let spot = LightType::Spot {
             position,
+            direction: direction.normalize(),
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+        };
+        Light::from_type(spot, intensity)
+    }
+    pub fn rectangle(corner: Point3d, edge_u: Vector3d, edge_v: Vector3d, intensity: f32) -> Light {
+        Light::from_type(LightType::Rectangle { corner, edge_u, edge_v }, intensity)
+    }
+    /// Evenly glowing ball, as bright from afar as a point light of the same intensity
+    pub fn sphere(center: Point3d, radius: f32, intensity: f32) -> Light {
+        Light::from_type(LightType::Sphere { center, radius }, intensity)
+    }
+
+    fn from_type(kind: LightType, intensity: f32) -> Light {
+        Light {
+            kind,
             intensity,
+            color: Color::white(),
+            num_samples: 16,
+        }
+    }
+
+    pub fn color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+    pub fn samples(mut self, num_samples: usize) -> Self {
+        self.num_samples = num_samples.max(1);
+        self
+    }
+
+    // Color and intensity combined
+    pub fn get_radiance(&self) -> Color {
+        self.color * self.intensity
+    }
+
+    /// Number of times to call sample() for a shaded point, only area lights need more than one
+    pub fn get_num_samples(&self) -> usize {
+        match self.kind {
+            LightType::Rectangle { .. } | LightType::Sphere { .. } => self.num_samples,
+            _ => 1,
+        }
+    }
+
+    /// Picks a point on the light, at random for area lights
+    pub fn sample(&self, surface_pt: Point3d) -> LightSample {
+        match self.kind {
+            LightType::Point { position } => self.sample_point(surface_pt, position, 1.0),
+            LightType::Directional { direction } => LightSample {
+                surface_to_light: -direction,
+                distance: f32::MAX,
+                radiance: self.get_radiance(),
+            },
+            LightType::Spot {
+                position,
+                direction,
+                cos_inner,
+                cos_outer,
+            } => {
+                let light_to_surface = (surface_pt - position).normalize();
+                let t = ((light_to_surface * direction - cos_outer) / (cos_inner - cos_outer))
+                    .clamp(0.0, 1.0);
+                let falloff = t * t * (3.0 - 2.0 * t); // smoothstep
+                self.sample_point(surface_pt, position, falloff)
+            }
+            LightType::Rectangle {
+                corner,
+                edge_u,
+                edge_v,
+            } => {
+                let light_pt = corner + edge_u * random_f32() + edge_v * random_f32();
+                let light_normal = edge_u.crossprod(&edge_v).normalize();
+                let mut sample = self.sample_point(surface_pt, light_pt, 1.0);
+                sample.radiance = sample.radiance * (sample.surface_to_light * light_normal).abs();
+                sample
+            }
+            LightType::Sphere { center, radius } => self.sample_sphere(surface_pt, center, radius),
+        }
+    }
+
+    // Uniformly within the cone of directions the sphere covers as seen from the surface point.
+    // Emitting the intensity over its cross-section, the sphere has the radiance
+    // I / (pi r^2) and every sample brings it times the solid angle of the cone.
+    fn sample_sphere(&self, surface_pt: Point3d, center: Point3d, radius: f32) -> LightSample {
+        let to_center = center - surface_pt;
+        let distance_squared = to_center * to_center;
+        let radiance = self.get_radiance() / (PI * radius * radius);
+        if distance_squared <= radius * radius {
+            // Inside, the sphere is all around
+            let surface_to_light = random_unit_vector();
+            let b = to_center * surface_to_light;
+            let distance = b + (b * b - distance_squared + radius * radius).sqrt();
+            return LightSample {
+                surface_to_light,
+                distance,
+                radiance: radiance * (4.0 * PI),
+            };
+        }
+
+        let sin_max_squared = radius * radius / distance_squared;
+        let cos_max = (1.0 - sin_max_squared).sqrt();
+        let cos_theta = 1.0 - random_f32() * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_f32();
+        let axis = to_center / distance_squared.sqrt();
+        let (tangent, bitangent) = get_orthonormal_basis(axis);
+        let surface_to_light = axis * cos_theta
+            + tangent * (sin_theta * phi.cos())
+            + bitangent * (sin_theta * phi.sin());
+        // Nearer of the two intersections, the one at the edge of the cone if rounding misses it
+        let b = to_center * surface_to_light;
+        let distance = b - (b * b - distance_squared + radius * radius).max(0.0).sqrt();
+        LightSample {
+            surface_to_light,
+            distance,
+            radiance: radiance * (2.0 * PI * (1.0 - cos_max)),
         }
     }
+
+    // Inverse-square attenuated light from a single point
+    fn sample_point(&self, surface_pt: Point3d, light_pt: Point3d, falloff: f32) -> LightSample {
+        let surface_to_light = light_pt - surface_pt;
+        let distance = surface_to_light.len();
+        LightSample {
+            surface_to_light: surface_to_light / distance,
+            distance,
+            radiance: self.get_radiance() * (falloff / (distance * distance)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn get_origin() -> Point3d {
+        Point3d::from_coords(0.0, 0.0, 0.0)
+    }
+
+    // Mean irradiance on a surface facing up from the origin
+    fn get_irradiance(light: &Light, surface_pt: Point3d, num_samples: usize) -> f32 {
+        let normal = Vector3d::from_coords(0.0, 1.0, 0.0);
+        let sum: f32 = (0..num_samples)
+            .map(|_| {
+                let sample = light.sample(surface_pt);
+                sample.radiance.g * (sample.surface_to_light * normal).max(0.0)
+            })
+            .sum();
+        sum / num_samples as f32
+    }
+
+    #[test]
+    fn t_point() {
+        let light = Light::point(Point3d::from_coords(0.0, 2.0, 0.0), 8.0).color(Color::grey(0.5));
+        let sample = light.sample(get_origin());
+        assert_eq!(sample.surface_to_light, Vector3d::from_coords(0.0, 1.0, 0.0));
+        assert_eq!(sample.distance, 2.0);
+        assert_eq!(sample.radiance, Color::grey(1.0));
+        assert_eq!(light.get_num_samples(), 1);
+    }
+
+    #[test]
+    fn t_directional() {
+        // No falloff and nothing beyond it, wherever the surface point is
+        let light = Light::directional(Vector3d::from_coords(0.0, -3.0, 0.0), 2.0);
+        for surface_pt in [get_origin(), Point3d::from_coords(100.0, -1000.0, 5.0)] {
+            let sample = light.sample(surface_pt);
+            assert_eq!(sample.surface_to_light, Vector3d::from_coords(0.0, 1.0, 0.0));
+            assert_eq!(sample.distance, f32::MAX);
+            assert_eq!(sample.radiance, Color::grey(2.0));
+        }
+    }
+
+    #[test]
+    fn t_spot() {
+        // Pointing down from 1 above the floor, fully lit up to 30 degrees, dark past 60
+        let position = Point3d::from_coords(0.0, 1.0, 0.0);
+        let light = Light::spot(position, Vector3d::from_coords(0.0, -1.0, 0.0), 30.0, 60.0, 1.0);
+        let get_falloff = |angle: f32| {
+            let surface_pt = Point3d::from_coords(angle.to_radians().tan(), 0.0, 0.0);
+            let distance = (surface_pt - position).len();
+            light.sample(surface_pt).radiance.g * distance * distance
+        };
+        assert!((get_falloff(0.0) - 1.0).abs() < EPSILON);
+        assert!((get_falloff(29.0) - 1.0).abs() < EPSILON);
+        assert_eq!(get_falloff(61.0), 0.0);
+        assert_eq!(get_falloff(80.0), 0.0);
+
+        // Smoothstep of the cosine in between, flat at both the ends
+        let (cos_inner, cos_outer) = (30f32.to_radians().cos(), 60f32.to_radians().cos());
+        let middle = ((cos_inner + cos_outer) / 2.0).acos().to_degrees();
+        assert!((get_falloff(middle) - 0.5).abs() < 1e-4);
+        let mut previous = 1.0;
+        for angle in [35.0, 40.0, 45.0, 50.0, 55.0] {
+            let falloff = get_falloff(angle);
+            assert!(falloff < previous && falloff > 0.0);
+            previous = falloff;
+        }
+        assert!(1.0 - get_falloff(30.5) < 0.01);
+        assert!(get_falloff(59.5) < 0.01);
+    }
+
+    #[test]
+    fn t_rectangle() {
+        // Unit square 1 above the origin, facing down
+        let corner = Point3d::from_coords(-0.5, 1.0, -0.5);
+        let edge_u = Vector3d::from_coords(1.0, 0.0, 0.0);
+        let edge_v = Vector3d::from_coords(0.0, 0.0, 1.0);
+        let light = Light::rectangle(corner, edge_u, edge_v, 1.0).samples(4);
+        assert_eq!(light.get_num_samples(), 4);
+
+        let num_samples = 100000;
+        let mut mean = Vector3d::new();
+        for _ in 0..num_samples {
+            let sample = light.sample(get_origin());
+            let light_pt = get_origin() + sample.surface_to_light * sample.distance;
+            assert!((light_pt.y - 1.0).abs() < EPSILON);
+            assert!(light_pt.x.abs() <= 0.5 + EPSILON && light_pt.z.abs() <= 0.5 + EPSILON);
+            mean = mean + (light_pt - get_origin()) / num_samples as f32;
+        }
+        assert!((mean - Vector3d::from_coords(0.0, 1.0, 0.0)).len() < 0.01);
+
+        // From far away it is a point light
+        let surface_pt = Point3d::from_coords(0.0, -99.0, 0.0);
+        let irradiance = get_irradiance(&light, surface_pt, 1000);
+        assert!((irradiance - 1e-4).abs() < 1e-7, "{}", irradiance);
+    }
+
+    #[test]
+    fn t_sphere() {
+        let center = Point3d::from_coords(0.0, 1.5, 0.0);
+        let light = Light::sphere(center, 1.0, 1.0);
+
+        // All the samples on the part of the sphere seen from the surface point
+        for _ in 0..10000 {
+            let sample = light.sample(get_origin());
+            let light_pt = get_origin() + sample.surface_to_light * sample.distance;
+            assert!(((light_pt - center).len() - 1.0).abs() < 1e-4);
+            assert!((light_pt - center) * sample.surface_to_light <= EPSILON);
+        }
+
+        // Spheres above the horizon light the surface as much as a point light at the center
+        let irradiance = get_irradiance(&light, get_origin(), 100000);
+        assert!((irradiance - 1.0 / (1.5 * 1.5)).abs() < 0.01, "{}", irradiance);
+        let light = Light::sphere(Point3d::from_coords(0.0, 10.0, 0.0), 3.0, 1.0);
+        let irradiance = get_irradiance(&light, get_origin(), 100000);
+        assert!((irradiance - 0.01).abs() < 1e-4, "{}", irradiance);
+    }
 }
-//}
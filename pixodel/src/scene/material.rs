@@ -0,0 +1,204 @@
+use std::sync::Arc;
+
+use geometry::Vector3d;
+
+use crate::scene::color::Color;
+use crate::scene::microfacet::MetallicRoughness;
+use crate::scene::texture::Texture;
+
+/// Surface properties of a [`SceneObj`](crate::scene::SceneObj), shared by all its primitives
+#[derive(Clone, Debug)]
+pub struct Material {
+    // Share of the incoming light the surface scatters back, the color of the object
+    pub albedo: Color,
+    pub ambient_reflection: f32,
+    pub diffuse_reflection: f32,
+    pub specular_reflection: f32,
+    // The higher, the smaller and sharper the specular highlights
+    pub shininess: f32,
+    // 0.0 - no reflections, 1.0 - perfect mirror
+    pub reflectivity: f32,
+    // Light given off by the surface itself, independent of the lights in the scene
+    pub emission: Color,
+    // Set for transparent dielectrics like glass or water
+    pub refraction_index: Option<f32>,
+    // Set for physically based materials, used by the Cook-Torrance shader and the path tracer
+    pub metallic_roughness: Option<MetallicRoughness>,
+    // Multiplies the albedo
+    pub diffuse_texture: Option<Arc<Texture>>,
+    // Multiplies the specular reflection by its luminance
+    pub specular_texture: Option<Arc<Texture>>,
+    // Tangent space normals
+    pub normal_texture: Option<Arc<Texture>>,
+    // Height map, used when there is no normal texture
+    pub bump_texture: Option<Arc<Texture>>,
+    // Height difference of the white and black texels of the bump texture, in texture coordinates
+    pub bump_scale: f32,
+}
+
+impl Material {
+    pub fn new() -> Self {
+        Material {
+            albedo: Color::white(),
+            ambient_reflection: 0.1,
+            diffuse_reflection: 1.0,
+            specular_reflection: 0.1,
+            shininess: 20.0,
+            reflectivity: 0.0,
+            emission: Color::black(),
+            refraction_index: None,
+            metallic_roughness: None,
+            diffuse_texture: None,
+            specular_texture: None,
+            normal_texture: None,
+            bump_texture: None,
+            bump_scale: 0.01,
+        }
+    }
+
+    pub fn albedo(mut self, albedo: Color) -> Self {
+        self.albedo = albedo;
+        self
+    }
+    pub fn ambient_reflection(mut self, ambient_reflection: f32) -> Self {
+        self.ambient_reflection = ambient_reflection;
+        self
+    }
+    pub fn diffuse_reflection(mut self, diffuse_reflection: f32) -> Self {
+        self.diffuse_reflection = diffuse_reflection;
+        self
+    }
+    pub fn specular_reflection(mut self, specular_reflection: f32) -> Self {
+        self.specular_reflection = specular_reflection;
+        self
+    }
+    pub fn shininess(mut self, shininess: f32) -> Self {
+        self.shininess = shininess;
+        self
+    }
+    pub fn reflectivity(mut self, reflectivity: f32) -> Self {
+        self.reflectivity = reflectivity;
+        self
+    }
+    pub fn emission(mut self, emission: Color) -> Self {
+        self.emission = emission;
+        self
+    }
+    /// Makes the material transparent, e.g. 1.5 for glass
+    pub fn dielectric(mut self, refraction_index: f32) -> Self {
+        self.refraction_index = Some(refraction_index);
+        self
+    }
+    /// glTF-like physically based material, the albedo being its base color
+    pub fn metallic_roughness(mut self, metallic: f32, roughness: f32) -> Self {
+        self.metallic_roughness = Some(MetallicRoughness::new(metallic, roughness));
+        self
+    }
+    pub fn diffuse_texture(mut self, texture: Arc<Texture>) -> Self {
+        self.diffuse_texture = Some(texture);
+        self
+    }
+    pub fn specular_texture(mut self, texture: Arc<Texture>) -> Self {
+        self.specular_texture = Some(texture);
+        self
+    }
+    pub fn normal_texture(mut self, texture: Arc<Texture>) -> Self {
+        self.normal_texture = Some(texture);
+        self
+    }
+    pub fn bump_texture(mut self, texture: Arc<Texture>, bump_scale: f32) -> Self {
+        self.bump_texture = Some(texture);
+        self.bump_scale = bump_scale;
+        self
+    }
+
+    /// Applies the normal or bump texture to the normal, if there is one. The tangent is the
+    /// direction of growing u along with the bitangent sign, the normal points outwards.
+    pub fn get_mapped_normal(
+        &self,
+        normal: Vector3d,
+        tangent: (Vector3d, f32),
+        tex_coords: [f32; 2],
+        footprint: f32,
+    ) -> Vector3d {
+        let (tangent, sign) = tangent;
+        let tangent = (tangent - normal * (normal * tangent)).normalize();
+        let bitangent = normal.crossprod(&tangent) * sign;
+
+        if let Some(texture) = &self.normal_texture {
+            // [0; 1] texel values encode [-1; 1] coordinates
+            let texel = texture.sample(tex_coords, footprint) * 2.0 - Color::white();
+            (tangent * texel.r + bitangent * texel.g + normal * texel.b).normalize()
+        } else if let Some(texture) = &self.bump_texture {
+            // Finite differences of the height over a texel
+            let [u, v] = tex_coords;
+            let (du, dv) = (1.0 / texture.get_width() as f32, 1.0 / texture.get_height() as f32);
+            let get_height = |u, v| texture.sample([u, v], footprint).get_luminance();
+            let height = get_height(u, v);
+            let dh_du = (get_height(u + du, v) - height) / du * self.bump_scale;
+            let dh_dv = (get_height(u, v + dv) - height) / dv * self.bump_scale;
+            (normal - tangent * dh_du - bitangent * dh_dv).normalize()
+        } else {
+            normal
+        }
+    }
+
+    /// The material at a single point of the surface, with the textures sampled into the plain
+    /// parameters. footprint is the size of the shaded area in texture coordinates.
+    pub fn get_textured(&self, tex_coords: [f32; 2], footprint: f32) -> Material {
+        let sample = |texture: &Option<Arc<Texture>>| {
+            texture.as_ref().map(|x| x.sample(tex_coords, footprint))
+        };
+        let albedo = match sample(&self.diffuse_texture) {
+            Some(texel) => self.albedo * texel,
+            None => self.albedo,
+        };
+        let specular_reflection = match sample(&self.specular_texture) {
+            Some(texel) => self.specular_reflection * texel.get_luminance(),
+            None => self.specular_reflection,
+        };
+        // Not cloning the textures, the Arc counters would be hammered by all the threads
+        Material {
+            albedo,
+            ambient_reflection: self.ambient_reflection,
+            diffuse_reflection: self.diffuse_reflection,
+            specular_reflection,
+            shininess: self.shininess,
+            reflectivity: self.reflectivity,
+            emission: self.emission,
+            refraction_index: self.refraction_index,
+            metallic_roughness: self.metallic_roughness,
+            diffuse_texture: None,
+            specular_texture: None,
+            normal_texture: None,
+            bump_texture: None,
+            bump_scale: self.bump_scale,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_textured() {
+        let texture = Arc::new(Texture::from_texels(1, 1, vec![Color::grey(0.5)]));
+        let material = Material::new()
+            .albedo(Color::new(1.0, 0.5, 0.25))
+            .diffuse_texture(texture.clone())
+            .bump_texture(texture, 0.2);
+        let textured = material.get_textured([0.5, 0.5], 0.0);
+        assert_eq!(textured.albedo, Color::new(0.5, 0.25, 0.125));
+        assert!(textured.diffuse_texture.is_none() && textured.bump_texture.is_none());
+        // The plain parameters carry over as they are
+        assert_eq!(textured.bump_scale, 0.2);
+        assert_eq!(textured.shininess, material.shininess);
+    }
+}
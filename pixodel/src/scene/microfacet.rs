@@ -0,0 +1,292 @@
+use std::f32::consts::PI;
+
+use geometry::Vector3d;
+
+use crate::scene::color::Color;
+use crate::scene::random::{get_orthonormal_basis, random_cosine_direction, random_f32};
+
+// Reflectance at normal incidence of dielectrics, glTF uses the same for all of them
+const DIELECTRIC_F0: f32 = 0.04;
+
+// Perfectly smooth surfaces make the GGX distribution a delta, which cannot be evaluated
+const MIN_ROUGHNESS: f32 = 0.03;
+
+/// Cook-Torrance BRDF with the GGX normal distribution, Smith masking-shadowing and Schlick
+/// Fresnel, parametrized the way glTF does it. The base color is the albedo of the material.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MetallicRoughness {
+    // 0.0 - dielectric, 1.0 - metal tinting its reflections with the base color
+    pub metallic: f32,
+    // Perceptual roughness, the GGX alpha is its square
+    pub roughness: f32,
+}
+
+impl MetallicRoughness {
+    pub fn new(metallic: f32, roughness: f32) -> Self {
+        MetallicRoughness {
+            metallic: metallic.clamp(0.0, 1.0),
+            roughness: roughness.clamp(MIN_ROUGHNESS, 1.0),
+        }
+    }
+
+    fn get_alpha(&self) -> f32 {
+        self.roughness * self.roughness
+    }
+
+    fn get_f0(&self, base_color: Color) -> Color {
+        Color::grey(DIELECTRIC_F0) * (1.0 - self.metallic) + base_color * self.metallic
+    }
+
+    /// Value of the BRDF for the unit vectors from the surface to the camera and to the light,
+    /// the cosine term is not included
+    pub fn eval(
+        &self,
+        base_color: Color,
+        normal: Vector3d,
+        surface_to_camera: Vector3d,
+        surface_to_light: Vector3d,
+    ) -> Color {
+        let n_dot_v = normal * surface_to_camera;
+        let n_dot_l = normal * surface_to_light;
+        if n_dot_v <= 0.0 || n_dot_l <= 0.0 {
+            return Color::black();
+        }
+        let halfway = (surface_to_camera + surface_to_light).normalize();
+        let alpha = self.get_alpha();
+
+        let f0 = self.get_f0(base_color);
+        let fresnel = schlick_fresnel(f0, surface_to_camera * halfway);
+        let specular = fresnel * (ggx_distribution(normal * halfway, alpha)
+            * smith_masking(n_dot_v, alpha)
+            * smith_masking(n_dot_l, alpha)
+            / (4.0 * n_dot_v * n_dot_l));
+        // Light not reflected by the surface gets scattered under it, metals absorb it. It passes
+        // the surface both on the way in and out, which keeps the sum with the specular
+        // reflection below 1 at grazing angles.
+        let transmitted = (Color::white() - schlick_fresnel(f0, n_dot_l))
+            * (Color::white() - schlick_fresnel(f0, n_dot_v));
+        let diffuse = transmitted * base_color * ((1.0 - self.metallic) / PI);
+        diffuse + specular
+    }
+
+    /// Importance samples the direction the light comes from, returns it together with the BRDF
+    /// times cosine divided by the pdf of picking it
+    pub fn sample(
+        &self,
+        base_color: Color,
+        normal: Vector3d,
+        surface_to_camera: Vector3d,
+    ) -> Option<(Vector3d, Color)> {
+        let surface_to_light = if random_f32() < self.get_specular_probability() {
+            let halfway = self.sample_halfway(normal);
+            halfway * (2.0 * (surface_to_camera * halfway)) - surface_to_camera
+        } else {
+            random_cosine_direction(normal)
+        };
+
+        let n_dot_l = normal * surface_to_light;
+        if n_dot_l <= 0.0 {
+            return None;
+        }
+        let pdf = self.get_pdf(normal, surface_to_camera, surface_to_light);
+        let brdf = self.eval(base_color, normal, surface_to_camera, surface_to_light);
+        Some((surface_to_light, brdf * (n_dot_l / pdf)))
+    }
+
+    // The diffuse lobe vanishes for metals, there is no point in sampling it
+    fn get_specular_probability(&self) -> f32 {
+        0.5 + 0.5 * self.metallic
+    }
+
+    // Microfacet normal distributed proportionally to D(h) * cos of its angle to the normal
+    fn sample_halfway(&self, normal: Vector3d) -> Vector3d {
+        let alpha = self.get_alpha();
+        let u = random_f32();
+        let cos_theta = ((1.0 - u) / (1.0 + u * (alpha * alpha - 1.0))).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_f32();
+        let (tangent, bitangent) = get_orthonormal_basis(normal);
+        tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + normal * cos_theta
+    }
+
+    // Combined pdf of both the lobes sample() picks from
+    fn get_pdf(
+        &self,
+        normal: Vector3d,
+        surface_to_camera: Vector3d,
+        surface_to_light: Vector3d,
+    ) -> f32 {
+        let halfway = (surface_to_camera + surface_to_light).normalize();
+        let n_dot_h = normal * halfway;
+        let v_dot_h = (surface_to_camera * halfway).max(1e-6);
+        let specular_pdf = ggx_distribution(n_dot_h, self.get_alpha()) * n_dot_h / (4.0 * v_dot_h);
+        let diffuse_pdf = (normal * surface_to_light) / PI;
+        let p = self.get_specular_probability();
+        p * specular_pdf + (1.0 - p) * diffuse_pdf
+    }
+}
+
+fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    if n_dot_h <= 0.0 {
+        return 0.0;
+    }
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * d * d)
+}
+
+// Share of the microfacets seen from the direction, the uncorrelated Smith G1 for GGX
+fn smith_masking(n_dot_x: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    2.0 * n_dot_x / (n_dot_x + (alpha2 + (1.0 - alpha2) * n_dot_x * n_dot_x).sqrt())
+}
+
+fn schlick_fresnel(f0: Color, cos: f32) -> Color {
+    f0 + (Color::white() - f0) * (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::random::random_unit_vector;
+
+    const ROUGHNESSES: [f32; 4] = [0.1, 0.3, 0.6, 1.0];
+
+    fn get_direction(angle: f32) -> Vector3d {
+        let angle = angle.to_radians();
+        Vector3d::from_coords(angle.sin(), angle.cos(), 0.0)
+    }
+
+    // Share of the light from everywhere the surface reflects towards the camera, by importance
+    // sampling
+    fn get_directional_albedo(brdf: &MetallicRoughness, base_color: Color, angle: f32) -> Color {
+        let (normal, surface_to_camera) = (get_direction(0.0), get_direction(angle));
+        let num_samples = 100000;
+        let mut sum = Color::black();
+        for _ in 0..num_samples {
+            if let Some((_, weight)) = brdf.sample(base_color, normal, surface_to_camera) {
+                sum += weight;
+            }
+        }
+        sum / num_samples as f32
+    }
+
+    #[test]
+    fn t_ggx_distribution() {
+        // The projected microfacet area adds up to the macro surface, D(h) cos over the hemisphere
+        // integrates to 1
+        let num_steps = 100000;
+        let step = PI / 2.0 / num_steps as f32;
+        for roughness in ROUGHNESSES {
+            let alpha = MetallicRoughness::new(0.0, roughness).get_alpha();
+            let integral: f32 = (0..num_steps)
+                .map(|i| {
+                    let theta = (i as f32 + 0.5) * step;
+                    let (sin, cos) = theta.sin_cos();
+                    2.0 * PI * ggx_distribution(cos, alpha) * cos * sin * step
+                })
+                .sum();
+            assert!((integral - 1.0).abs() < 1e-3, "{} for roughness {}", integral, roughness);
+        }
+        assert_eq!(ggx_distribution(-0.5, 0.5), 0.0);
+        // Uniform over the hemisphere for alpha = 1
+        assert!((ggx_distribution(0.3, 1.0) - 1.0 / PI).abs() < 1e-6);
+    }
+
+    #[test]
+    fn t_smith_masking() {
+        for roughness in ROUGHNESSES {
+            let alpha = MetallicRoughness::new(0.0, roughness).get_alpha();
+            assert!((smith_masking(1.0, alpha) - 1.0).abs() < 1e-6);
+            assert_eq!(smith_masking(0.0, alpha), 0.0);
+            // Grazing angles hide more of the rougher surfaces
+            assert!(smith_masking(0.2, alpha) < 1.0);
+            assert!(smith_masking(0.2, alpha) > smith_masking(0.2, alpha + 0.1));
+        }
+    }
+
+    #[test]
+    fn t_schlick_fresnel() {
+        let f0 = Color::new(0.04, 0.5, 1.0);
+        assert_eq!(schlick_fresnel(f0, 1.0), f0);
+        assert_eq!(schlick_fresnel(f0, 0.0), Color::white());
+        let half = schlick_fresnel(f0, 0.5);
+        assert!((half.r - (0.04 + 0.96 / 32.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn t_sample_weight() {
+        let base_color = Color::new(0.9, 0.5, 0.2);
+        let normal = get_direction(0.0);
+        for (metallic, roughness) in [(0.0, 0.2), (0.5, 0.5), (1.0, 0.8)] {
+            let brdf = MetallicRoughness::new(metallic, roughness);
+            for angle in [0.0, 30.0, 75.0] {
+                let surface_to_camera = get_direction(angle);
+                for _ in 0..1000 {
+                    let Some((dir, weight)) = brdf.sample(base_color, normal, surface_to_camera)
+                    else {
+                        continue;
+                    };
+                    let cos = normal * dir;
+                    let pdf = brdf.get_pdf(normal, surface_to_camera, dir);
+                    let brdf_value = brdf.eval(base_color, normal, surface_to_camera, dir);
+                    let expected = brdf_value * cos / pdf;
+                    assert!((weight - expected).get_luminance().abs() < 1e-4 * expected.r.max(1.0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn t_sample_pdf() {
+        // Importance sampling finds the same reflected light as uniform sampling of the
+        // hemisphere, i.e. the directions really are distributed according to get_pdf()
+        let base_color = Color::new(0.9, 0.5, 0.2);
+        let (normal, surface_to_camera) = (get_direction(0.0), get_direction(40.0));
+        for (metallic, roughness) in [(0.0, 0.5), (1.0, 0.5), (1.0, 1.0)] {
+            let brdf = MetallicRoughness::new(metallic, roughness);
+            let importance = get_directional_albedo(&brdf, base_color, 40.0);
+
+            let num_samples = 400000;
+            let mut uniform = Color::black();
+            for _ in 0..num_samples {
+                let mut dir = random_unit_vector();
+                if dir * normal < 0.0 {
+                    dir = -dir;
+                }
+                uniform += brdf.eval(base_color, normal, surface_to_camera, dir) * (normal * dir);
+            }
+            let uniform = uniform * (2.0 * PI / num_samples as f32);
+            let error = (importance.r - uniform.r).abs();
+            assert!(error < 0.02, "{:?} != {:?} for {:?}", importance, uniform, brdf);
+        }
+    }
+
+    #[test]
+    fn t_white_furnace() {
+        // A white surface under a uniform light reflects at most as much as it receives
+        for metallic in [0.0, 1.0] {
+            for roughness in ROUGHNESSES {
+                let brdf = MetallicRoughness::new(metallic, roughness);
+                for angle in [0.0, 45.0, 80.0] {
+                    let albedo = get_directional_albedo(&brdf, Color::white(), angle);
+                    assert!(albedo.r <= 1.01, "{} for {:?} at {}", albedo.r, brdf, angle);
+                    // Single scattering loses the light bouncing between the microfacets
+                    assert!(albedo.r > 0.25, "{} for {:?} at {}", albedo.r, brdf, angle);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn t_metal_at_normal_incidence() {
+        // Metals reflect their base color head on
+        let base_color = Color::new(0.9, 0.5, 0.2);
+        let brdf = MetallicRoughness::new(1.0, 0.0);
+        assert_eq!(schlick_fresnel(brdf.get_f0(base_color), 1.0), base_color);
+        let albedo = get_directional_albedo(&brdf, base_color, 0.0);
+        assert!((albedo.r - base_color.r).abs() < 0.01, "{:?}", albedo);
+        assert!((albedo.g - base_color.g).abs() < 0.01, "{:?}", albedo);
+        assert!((albedo.b - base_color.b).abs() < 0.01, "{:?}", albedo);
+    }
+}
@@ -0,0 +1,329 @@
+use std::f32::consts::PI;
+
+use geometry::{HitRecord, Ray3d, Vector3d};
+use rayon::prelude::*;
+
+use crate::framebuffer::Framebuffer;
+use crate::scene::color::Color;
+use crate::scene::material::Material;
+use crate::scene::microfacet::MetallicRoughness;
+use crate::scene::random::{random_cosine_direction, random_f32};
+use crate::scene::shading::is_in_shadow;
+use crate::scene::{fresnel, reflection_dir, refraction_dir, Scene, SECONDARY_RAY_OFFSET};
+
+/// Monte Carlo estimator of the light coming along camera rays, including all the interreflections
+/// between the objects. Diffuse surfaces are sampled proportionally to the cosine,
+/// metallic-roughness ones according to their BRDF, direct light gets sampled explicitly at every
+/// bounce, paths are cut with Russian roulette.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PathTracer {
+    // Hard limit on the number of bounces
+    max_depth: usize,
+    // Bounces before Russian roulette may terminate the path
+    roulette_depth: usize,
+}
+
+// How the path continues from a surface
+enum Bounce {
+    // Perfect mirror reflection or refraction, direct light sampling does not apply
+    Specular(Vector3d),
+    Diffuse,
+    Microfacet(MetallicRoughness),
+}
+
+impl PathTracer {
+    pub fn new() -> Self {
+        PathTracer {
+            max_depth: 16,
+            roulette_depth: 3,
+        }
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+    pub fn roulette_depth(mut self, roulette_depth: usize) -> Self {
+        self.roulette_depth = roulette_depth;
+        self
+    }
+
+    /// Adds one more sample to every pixel of the running average in fbuf, pass_idx being the
+    /// number of samples it already holds. generate_ray is given the pixel and a random offset
+    /// within it. The sample pattern and the pixel filter of a
+    /// [`Supersampler`](crate::antialiasing::Supersampler) do not apply: the samples are uniformly
+    /// spread over the pixel and box filtered, the progressive passes being their own
+    /// supersampling.
+    pub fn render_pass<G>(
+        &self,
+        scene: &Scene,
+        fbuf: &mut Framebuffer,
+        pass_idx: u32,
+        generate_ray: G,
+    ) where
+        G: Fn([u32; 2], [f32; 2]) -> Ray3d + Sync,
+    {
+        let width = fbuf.get_width();
+        let weight = 1.0 / (pass_idx + 1) as f32;
+        fbuf.get_pixels_mut().par_iter_mut().enumerate().for_each(|(idx, pix)| {
+            let pixel = [idx as u32 % width, idx as u32 / width];
+            let ray = generate_ray(pixel, [random_f32(), random_f32()]);
+            let sample = self.trace(scene, &ray);
+            *pix += (sample - *pix) * weight;
+        });
+    }
+
+    /// Single sample estimate of the radiance arriving along the ray
+    pub fn trace(&self, scene: &Scene, ray: &Ray3d) -> Color {
+        let lbvh = scene.get_lbvh();
+        let mut radiance = Color::black();
+        // Share of the light found further along the path that makes it to the camera
+        let mut throughput = Color::white();
+        let mut ray = *ray;
+
+        for depth in 0..self.max_depth {
+            let hit = match lbvh.traverse(&ray) {
+                Some(hit) => hit,
+                None => {
+                    radiance += throughput * scene.get_background();
+                    break;
+                }
+            };
+            let material = &scene.get_material_at(&hit);
+            let surface_normal = scene.get_surface_normal(&hit);
+            let incident_dir = ray.get_direction().normalize();
+
+            // Emissive objects are not sampled as lights, so their light is only found this way
+            radiance += throughput * material.emission;
+
+            let bounce = PathTracer::choose_bounce(&hit, surface_normal, incident_dir, material);
+            let next_dir = match bounce {
+                Bounce::Specular(dir) => dir,
+                Bounce::Diffuse => {
+                    let reflectance = material.albedo * material.diffuse_reflection;
+                    let brdf = |_: Vector3d| reflectance / PI;
+                    let direct = PathTracer::sample_lights(scene, &hit, surface_normal, brdf);
+                    radiance += throughput * direct;
+                    throughput = throughput * reflectance; // BRDF * cos / pdf
+                    random_cosine_direction(surface_normal)
+                }
+                Bounce::Microfacet(mr) => {
+                    let surface_to_camera = -incident_dir;
+                    let albedo = material.albedo;
+                    let brdf = |surface_to_light: Vector3d| {
+                        mr.eval(albedo, surface_normal, surface_to_camera, surface_to_light)
+                    };
+                    let direct = PathTracer::sample_lights(scene, &hit, surface_normal, brdf);
+                    radiance += throughput * direct;
+                    match mr.sample(material.albedo, surface_normal, surface_to_camera) {
+                        Some((dir, weight)) => {
+                            throughput = throughput * weight;
+                            dir
+                        }
+                        None => break,
+                    }
+                }
+            };
+
+            if depth + 1 >= self.roulette_depth {
+                let survival = throughput.r.max(throughput.g).max(throughput.b).min(1.0);
+                if random_f32() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+
+            // Continue from the side of the surface the new direction points to
+            let offset = if next_dir * surface_normal > 0.0 {
+                surface_normal * SECONDARY_RAY_OFFSET
+            } else {
+                surface_normal * -SECONDARY_RAY_OFFSET
+            };
+            ray = Ray3d::from(hit.point + offset, next_dir).with_time(hit.time);
+        }
+        radiance
+    }
+
+    // Picks randomly between the ways the material scatters the light, weighted by their shares so
+    // that the throughput stays the same
+    fn choose_bounce(
+        hit: &HitRecord,
+        surface_normal: Vector3d,
+        incident_dir: Vector3d,
+        material: &Material,
+    ) -> Bounce {
+        let mirror_dir = reflection_dir(surface_normal, -incident_dir).normalize();
+
+        if let Some(refraction_index) = material.refraction_index {
+            let eta = if hit.front_face { 1.0 / refraction_index } else { refraction_index };
+            return match refraction_dir(surface_normal, incident_dir, eta) {
+                Some(refr_dir) if random_f32() >= fresnel(surface_normal, incident_dir, eta) => {
+                    Bounce::Specular(refr_dir.normalize())
+                }
+                _ => Bounce::Specular(mirror_dir),
+            };
+        }
+
+        if let Some(metallic_roughness) = material.metallic_roughness {
+            return Bounce::Microfacet(metallic_roughness);
+        }
+
+        if random_f32() < material.reflectivity {
+            Bounce::Specular(mirror_dir)
+        } else {
+            Bounce::Diffuse
+        }
+    }
+
+    // Next event estimation: the light reaching the surface point directly from all the lights,
+    // reflected according to the BRDF given the unit vector from the surface to the light
+    fn sample_lights<B>(scene: &Scene, hit: &HitRecord, surface_normal: Vector3d, brdf: B) -> Color
+    where
+        B: Fn(Vector3d) -> Color,
+    {
+        let lbvh = scene.get_lbvh();
+        let mut reflected = Color::black();
+        for l in &scene.lights {
+            let num_samples = l.get_num_samples();
+            for _ in 0..num_samples {
+                let sample = l.sample(hit.point);
+                let surface_to_light = sample.surface_to_light;
+                let cos = surface_to_light * surface_normal;
+                if cos > 0.0 && !is_in_shadow(hit.point, surface_normal, &sample, lbvh, hit.time) {
+                    let brdf = brdf(surface_to_light);
+                    reflected += sample.radiance * brdf * (cos / num_samples as f32);
+                }
+            }
+        }
+        reflected
+    }
+}
+
+impl Default for PathTracer {
+    fn default() -> Self {
+        PathTracer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{Light, SceneObj, SphereObj, TriObj};
+    use geometry::sphere::Sphere;
+    use geometry::triangle::Triangle;
+    use geometry::Point3d;
+    use std::sync::Arc;
+
+    fn get_sphere(center: Point3d, radius: f32, material: Material) -> SceneObj {
+        SceneObj::new(Arc::new(SphereObj::new(Sphere::new(center, radius))))
+            .scale(1.0, 1.0, 1.0)
+            .material(material)
+    }
+
+    fn get_ray(origin: Point3d) -> Ray3d {
+        Ray3d::from(origin, Vector3d::from_coords(0.0, 0.0, -1.0))
+    }
+
+    // Camera inside a closed sphere, albedo 1/2 and emitting 1/2: every bounce adds half of the
+    // light of the previous one, L = E / (1 - albedo) = 1
+    fn get_enclosure() -> Scene {
+        let material = Material::new().albedo(Color::grey(0.5)).emission(Color::grey(0.5));
+        Scene::new()
+            .background(Color::black())
+            .add_obj(get_sphere(Point3d::from_coords(0.0, 0.0, 0.0), 10.0, material))
+    }
+
+    fn get_mean_radiance(path_tracer: &PathTracer, scene: &Scene, num_samples: usize) -> f32 {
+        let ray = get_ray(Point3d::from_coords(0.0, 0.0, 0.0));
+        let sum: f32 = (0..num_samples).map(|_| path_tracer.trace(scene, &ray).g).sum();
+        sum / num_samples as f32
+    }
+
+    #[test]
+    fn t_white_furnace() {
+        // A white diffuse sphere under a uniform sky reflects all of it, the sphere vanishes
+        let scene = Scene::new()
+            .background(Color::white())
+            .add_obj(get_sphere(Point3d::from_coords(0.0, 0.0, -5.0), 1.0, Material::new()));
+        let path_tracer = PathTracer::new();
+        for x in [0.0, 0.5, 0.99, 2.0] {
+            let ray = get_ray(Point3d::from_coords(x, 0.0, 0.0));
+            for _ in 0..100 {
+                let radiance = path_tracer.trace(&scene, &ray);
+                assert!((radiance.g - 1.0).abs() < 1e-4, "{:?} at {}", radiance, x);
+            }
+        }
+
+        // Without Russian roulette the geometric series is only cut after max_depth bounces
+        let scene = get_enclosure();
+        let path_tracer = PathTracer::new().max_depth(16).roulette_depth(16);
+        let expected = 1.0 - 0.5_f32.powi(16);
+        assert!((get_mean_radiance(&path_tracer, &scene, 10) - expected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn t_russian_roulette() {
+        // Terminating the paths early adds noise, the mean stays the same
+        let scene = get_enclosure();
+        let expected = 1.0 - 0.5_f32.powi(16);
+        for roulette_depth in [1, 3, 16] {
+            let path_tracer = PathTracer::new().max_depth(16).roulette_depth(roulette_depth);
+            let mean = get_mean_radiance(&path_tracer, &scene, 20000);
+            assert!((mean - expected).abs() < 0.02, "{} at depth {}", mean, roulette_depth);
+        }
+    }
+
+    #[test]
+    fn t_next_event_estimation() {
+        // White diffuse floor lit by a point light 2 above the origin
+        let floor = Triangle::new(
+            Point3d::from_coords(-100.0, 0.0, 100.0),
+            Point3d::from_coords(100.0, 0.0, 100.0),
+            Point3d::from_coords(0.0, 0.0, -100.0),
+        );
+        let (height, intensity) = (2.0, 10.0);
+        let scene = Scene::new()
+            .background(Color::black())
+            .add_obj(SceneObj::new(Arc::new(TriObj::new(floor))).scale(1.0, 1.0, 1.0))
+            .add_light(Light::point(Point3d::from_coords(0.0, height, 0.0), intensity));
+        let path_tracer = PathTracer::new();
+
+        for x in [0.0, 1.0, 3.0] {
+            let origin = Point3d::from_coords(x, 1.0, 0.0);
+            let ray = Ray3d::from(origin, Vector3d::from_coords(0.0, -1.0, 0.0));
+            let hit = scene.get_lbvh().traverse(&ray).unwrap();
+            let normal = scene.get_surface_normal(&hit);
+            assert_eq!(normal, Vector3d::from_coords(0.0, 1.0, 0.0));
+
+            // Irradiance of a point light, I cos / d^2
+            let distance_squared = x * x + height * height;
+            let irradiance = intensity * height / distance_squared.sqrt() / distance_squared;
+            let direct = PathTracer::sample_lights(&scene, &hit, normal, |_| Color::white());
+            assert!((direct.g - irradiance).abs() < 1e-4 * irradiance);
+
+            // All the light bouncing off the floor escapes, only the direct light is left
+            let radiance = path_tracer.trace(&scene, &ray);
+            assert!((radiance.g - irradiance / PI).abs() < 1e-4 * irradiance);
+        }
+    }
+
+    #[test]
+    fn t_render_pass() {
+        let scene = Scene::new()
+            .background(Color::white())
+            .add_obj(get_sphere(Point3d::from_coords(0.0, 0.0, -5.0), 1.0, Material::new()));
+        let mut fbuf = Framebuffer::new(4, 3);
+        let generate_ray = |pixel: [u32; 2], offset: [f32; 2]| {
+            assert!((0.0..1.0).contains(&offset[0]) && (0.0..1.0).contains(&offset[1]));
+            let x = (pixel[0] as f32 + offset[0]) * 0.5 - 1.0;
+            let y = (pixel[1] as f32 + offset[1]) * 0.5 - 0.75;
+            get_ray(Point3d::from_coords(x, y, 0.0))
+        };
+        for pass_idx in 0..4 {
+            PathTracer::new().render_pass(&scene, &mut fbuf, pass_idx, generate_ray);
+        }
+        for pixel in fbuf.get_pixels() {
+            assert!((pixel.g - 1.0).abs() < 1e-4, "{:?}", pixel);
+        }
+    }
+}
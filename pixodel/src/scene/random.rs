@@ -0,0 +1,54 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use geometry::Vector3d;
+
+// Every rendering thread gets its own generator, seeded from a shared counter so that the
+// sequences differ
+static NEXT_SEED: AtomicU64 = AtomicU64::new(0x9e37_79b9_7f4a_7c15);
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(NEXT_SEED.fetch_add(0x6a09_e667_f3bc_c909, Ordering::Relaxed) | 1);
+}
+
+/// Uniformly distributed in [0; 1), xorshift64* - fast, not meant for anything but sampling
+pub fn random_f32() -> f32 {
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        // The upper 24 bits fit into the f32 mantissa exactly
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 40) as f32 / (1u64 << 24) as f32
+    })
+}
+
+/// Uniformly distributed on the unit sphere
+pub fn random_unit_vector() -> Vector3d {
+    let z = 1.0 - 2.0 * random_f32();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * random_f32();
+    Vector3d::from_coords(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Distributed around the normal proportionally to the cosine of the angle to it, the pdf is
+/// cos / PI
+pub fn random_cosine_direction(normal: Vector3d) -> Vector3d {
+    let r = random_f32().sqrt();
+    let phi = 2.0 * std::f32::consts::PI * random_f32();
+    let z = (1.0 - r * r).max(0.0).sqrt();
+    let (tangent, bitangent) = get_orthonormal_basis(normal);
+    tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * z
+}
+
+/// Two unit vectors perpendicular to the unit normal and to each other (Duff et al. 2017)
+pub fn get_orthonormal_basis(normal: Vector3d) -> (Vector3d, Vector3d) {
+    let sign = 1.0_f32.copysign(normal.z);
+    let a = -1.0 / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+    (
+        Vector3d::from_coords(1.0 + sign * normal.x * normal.x * a, sign * b, -sign * normal.x),
+        Vector3d::from_coords(b, sign + normal.y * normal.y * a, -normal.y),
+    )
+}
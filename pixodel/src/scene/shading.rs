@@ -1,31 +1,287 @@
-use geometry::{Point3d, Vector3d};
-use crate::scene::light::Light;
+use geometry::{Point3d, Ray3d, Vector3d};
+use crate::scene::color::Color;
+use crate::scene::light::{Light, LightSample};
+use crate::scene::material::Material;
+use crate::scene::microfacet::MetallicRoughness;
+use crate::scene::{reflection_dir, SceneLbvh, SECONDARY_RAY_OFFSET};
 
-pub fn phong(
+/// Local illumination model, gives the light a surface point sends back along the ray which hit it.
+/// The shadow rays are cast at the time of that ray.
+pub trait Shader: Send + Sync {
+    fn shade(
+        &self,
+        surface_pt: Point3d,
+        ray: &Ray3d,
+        surface_normal: Vector3d,
+        material: &Material,
+        lights: &[Light],
+        lbvh: &SceneLbvh,
+    ) -> Color;
+}
+
+/// Custom models can be plain closures
+impl<F> Shader for F
+where
+    F: Fn(Point3d, &Ray3d, Vector3d, &Material, &[Light], &SceneLbvh) -> Color + Send + Sync,
+{
+    fn shade(
+        &self,
+        surface_pt: Point3d,
+        ray: &Ray3d,
+        surface_normal: Vector3d,
+        material: &Material,
+        lights: &[Light],
+        lbvh: &SceneLbvh,
+    ) -> Color {
+        self(surface_pt, ray, surface_normal, material, lights, lbvh)
+    }
+}
+
+/// Diffuse only
+pub struct Lambert;
+
+/// Specular highlights from the angle between the camera and the reflected light
+pub struct Phong;
+
+/// Specular highlights from the angle between the normal and the half-way vector, cheaper than
+/// Phong and without its cut-off highlights at grazing angles
+pub struct BlinnPhong;
+
+/// Physically based microfacet model driven by the metallic-roughness parameters of the material
+pub struct CookTorrance;
+
+impl Shader for Lambert {
+    fn shade(
+        &self,
+        surface_pt: Point3d,
+        ray: &Ray3d,
+        surface_normal: Vector3d,
+        material: &Material,
+        lights: &[Light],
+        lbvh: &SceneLbvh,
+    ) -> Color {
+        let reflected = |_: Vector3d, _: Vector3d, diffuse_factor: f32| {
+            material.albedo * (diffuse_factor * material.diffuse_reflection)
+        };
+        shade_direct(surface_pt, ray, surface_normal, material, lights, lbvh, reflected)
+    }
+}
+
+impl Shader for Phong {
+    fn shade(
+        &self,
+        surface_pt: Point3d,
+        ray: &Ray3d,
+        surface_normal: Vector3d,
+        material: &Material,
+        lights: &[Light],
+        lbvh: &SceneLbvh,
+    ) -> Color {
+        let reflected = |surface_to_light: Vector3d, surface_to_camera: Vector3d, diffuse_factor| {
+            // cos of the camera to reflected ray angle
+            let reflected = reflection_dir(surface_normal, surface_to_light);
+            let specular_factor = (reflected * surface_to_camera).max(0.0).powf(material.shininess);
+            reflect_phong(material, diffuse_factor, specular_factor)
+        };
+        shade_direct(surface_pt, ray, surface_normal, material, lights, lbvh, reflected)
+    }
+}
+
+impl Shader for BlinnPhong {
+    fn shade(
+        &self,
+        surface_pt: Point3d,
+        ray: &Ray3d,
+        surface_normal: Vector3d,
+        material: &Material,
+        lights: &[Light],
+        lbvh: &SceneLbvh,
+    ) -> Color {
+        let reflected = |surface_to_light: Vector3d, surface_to_camera: Vector3d, diffuse_factor| {
+            let halfway = (surface_to_light + surface_to_camera).normalize();
+            let specular_factor = (halfway * surface_normal).max(0.0).powf(material.shininess);
+            reflect_phong(material, diffuse_factor, specular_factor)
+        };
+        shade_direct(surface_pt, ray, surface_normal, material, lights, lbvh, reflected)
+    }
+}
+
+impl Shader for CookTorrance {
+    fn shade(
+        &self,
+        surface_pt: Point3d,
+        ray: &Ray3d,
+        surface_normal: Vector3d,
+        material: &Material,
+        lights: &[Light],
+        lbvh: &SceneLbvh,
+    ) -> Color {
+        // Materials without the parameters are treated as rough dielectrics
+        let brdf = material
+            .metallic_roughness
+            .unwrap_or(MetallicRoughness::new(0.0, 1.0));
+        let reflected = |surface_to_light: Vector3d, surface_to_camera: Vector3d, diffuse_factor| {
+            brdf.eval(material.albedo, surface_normal, surface_to_camera, surface_to_light)
+                * diffuse_factor
+        };
+        shade_direct(surface_pt, ray, surface_normal, material, lights, lbvh, reflected)
+    }
+}
+
+fn reflect_phong(material: &Material, diffuse_factor: f32, specular_factor: f32) -> Color {
+    material.albedo * (diffuse_factor * material.diffuse_reflection)
+        + Color::grey(specular_factor * material.specular_reflection)
+}
+
+// Ambient and emitted light plus the reflections of all the unshadowed light samples. The reflected
+// share of the light is given the unit vectors from the surface to the light and to the camera and
+// the cos of the light to normal angle.
+fn shade_direct<R>(
     surface_pt: Point3d,
-    camera_pt: Point3d,
+    ray: &Ray3d,
     surface_normal: Vector3d,
-    lights: &Vec<Light>,
-) -> f32 {
-    let _shininess: f32 = 20.0; //TODO: use object material instead
-    let diffuse_reflection: f32 = 1.0; //TODO: use object material instead
-    let _specular_reflection: f32 = 0.1; //TODO: use object material instead
-    let ambient_reflection: f32 = 0.1; //TODO: use object material instead
-
-    let _surface_to_camera = (camera_pt - surface_pt).normalize();
+    material: &Material,
+    lights: &[Light],
+    lbvh: &SceneLbvh,
+    reflected: R,
+) -> Color
+where
+    R: Fn(Vector3d, Vector3d, f32) -> Color,
+{
+    let surface_to_camera = (ray.get_origin() - surface_pt).normalize();
 
-    let mut illumination = ambient_reflection;
+    let mut illumination = material.emission + material.albedo * material.ambient_reflection;
     for l in lights {
-        let surface_to_light = (l.position - surface_pt).normalize();
-        let diffuse_factor = surface_to_light * surface_normal; // cos of the light to normal angle
-        if diffuse_factor > 0.0 {
-            // let specular_factor = reflection_dir * surface_to_camera; // cos of the camera to reflected ray angle
-            // let mut specular_factor = specular_factor.powf(shininess);
-            // if specular_factor < 0.0 {
-            // 	specular_factor = 0.0;
-            // }
-            illumination += diffuse_factor * diffuse_reflection; // + specular_factor * specular_reflection;
+        let num_samples = l.get_num_samples();
+        for _ in 0..num_samples {
+            let sample = l.sample(surface_pt);
+            let surface_to_light = sample.surface_to_light;
+            let diffuse_factor = surface_to_light * surface_normal; // cos of the light to normal angle
+            if diffuse_factor > 0.0
+                && !is_in_shadow(surface_pt, surface_normal, &sample, lbvh, ray.get_time())
+            {
+                let reflected = reflected(surface_to_light, surface_to_camera, diffuse_factor);
+                illumination += sample.radiance * reflected / num_samples as f32;
+            }
         }
     }
     illumination
 }
+
+// Casts a shadow ray from the surface to the light. The ray starts off the surface and stops short
+// of the light so that neither the shaded primitive nor one the light sits on block it. Moving
+// objects cast their shadows as they are at the time.
+pub(crate) fn is_in_shadow(
+    surface_pt: Point3d,
+    surface_normal: Vector3d,
+    sample: &LightSample,
+    lbvh: &SceneLbvh,
+    time: f32,
+) -> bool {
+    let shadow_ray_origin = surface_pt + surface_normal * SECONDARY_RAY_OFFSET;
+    let shadow_ray = Ray3d::from(shadow_ray_origin, sample.surface_to_light).with_time(time);
+    lbvh.occluded(&shadow_ray, sample.distance - 2.0 * SECONDARY_RAY_OFFSET)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{Scene, SceneObj, SphereObj, TriObj};
+    use geometry::sphere::Sphere;
+    use geometry::triangle::Triangle;
+    use std::sync::Arc;
+
+    const EPSILON: f32 = 1e-5;
+
+    // Floor through the origin with a point light above it
+    fn get_scene(light_pt: Point3d, intensity: f32) -> Scene {
+        let floor = Triangle::new(
+            Point3d::from_coords(-100.0, 0.0, 100.0),
+            Point3d::from_coords(100.0, 0.0, 100.0),
+            Point3d::from_coords(0.0, 0.0, -100.0),
+        );
+        Scene::new()
+            .add_obj(SceneObj::new(Arc::new(TriObj::new(floor))).scale(1.0, 1.0, 1.0))
+            .add_light(Light::point(light_pt, intensity))
+    }
+
+    fn add_sphere(scene: Scene, center: Point3d, radius: f32) -> Scene {
+        let sphere = SphereObj::new(Sphere::new(center, radius));
+        scene.add_obj(SceneObj::new(Arc::new(sphere)).scale(1.0, 1.0, 1.0))
+    }
+
+    // Shading of the origin seen from the camera
+    fn shade<S>(shader: &S, scene: &Scene, camera_pt: Point3d, material: &Material) -> Color
+    where
+        S: Shader,
+    {
+        let surface_pt = Point3d::from_coords(0.0, 0.0, 0.0);
+        let ray = Ray3d::from(camera_pt, (surface_pt - camera_pt).normalize());
+        let normal = Vector3d::from_coords(0.0, 1.0, 0.0);
+        shader.shade(surface_pt, &ray, normal, material, &scene.lights, scene.get_lbvh())
+    }
+
+    #[test]
+    fn t_shadows() {
+        let light_pt = Point3d::from_coords(0.0, 2.0, 0.0);
+        let camera_pt = Point3d::from_coords(0.0, 1.0, 1.0);
+        let material = Material::new();
+        // Ambient plus the light of 4 / 2^2 straight on
+        let lit = shade(&Lambert, &get_scene(light_pt, 4.0), camera_pt, &material);
+        assert!((lit.g - 1.1).abs() < EPSILON, "{:?}", lit);
+
+        // Between the light and the surface
+        let scene = add_sphere(get_scene(light_pt, 4.0), Point3d::from_coords(0.0, 1.0, 0.0), 0.2);
+        let shadowed = shade(&Lambert, &scene, camera_pt, &material);
+        assert!((shadowed.g - 0.1).abs() < EPSILON, "{:?}", shadowed);
+
+        // Beyond the light, or with the light right on its surface
+        for (center, radius) in [(3.0, 0.2), (2.5, 0.5)] {
+            let center = Point3d::from_coords(0.0, center, 0.0);
+            let scene = add_sphere(get_scene(light_pt, 4.0), center, radius);
+            assert_eq!(shade(&Lambert, &scene, camera_pt, &material), lit);
+        }
+
+        // Off to the side
+        let scene = add_sphere(get_scene(light_pt, 4.0), Point3d::from_coords(1.0, 1.0, 0.0), 0.2);
+        assert_eq!(shade(&Lambert, &scene, camera_pt, &material), lit);
+    }
+
+    #[test]
+    fn t_specular() {
+        // Light coming in at 45 degrees, 1 of it reaching the surface
+        let scene = get_scene(Point3d::from_coords(-1.0, 1.0, 0.0), 2.0);
+        let material = Material::new()
+            .albedo(Color::new(1.0, 0.0, 0.0))
+            .ambient_reflection(0.0)
+            .diffuse_reflection(0.5)
+            .specular_reflection(0.4)
+            .shininess(20.0);
+        let diffuse = 0.5 * std::f32::consts::FRAC_1_SQRT_2;
+        // Camera at the angle from the mirror direction, within the plane of incidence
+        let get_specular = |shader: &dyn Fn(Point3d) -> Color, angle: f32| {
+            let angle = (45.0 - angle).to_radians();
+            let color = shader(Point3d::from_coords(angle.sin(), angle.cos(), 0.0));
+            assert!((color.r - color.g - diffuse).abs() < EPSILON, "{:?}", color);
+            color.g
+        };
+        let phong = |camera_pt| shade(&Phong, &scene, camera_pt, &material);
+        let blinn_phong = |camera_pt| shade(&BlinnPhong, &scene, camera_pt, &material);
+
+        // Peaks in the mirror direction, where the halfway vector is the normal
+        assert!((get_specular(&phong, 0.0) - 0.4).abs() < EPSILON);
+        assert!((get_specular(&blinn_phong, 0.0) - 0.4).abs() < EPSILON);
+        // Off the peak, Phong falls off with the angle to the reflected light and Blinn-Phong
+        // with the one of the halfway vector to the normal, half of it
+        for angle in [5.0_f32, 10.0, 20.0] {
+            let expected = 0.4 * angle.to_radians().cos().powf(20.0);
+            assert!((get_specular(&phong, angle) - expected).abs() < EPSILON);
+            let expected = 0.4 * (angle / 2.0).to_radians().cos().powf(20.0);
+            assert!((get_specular(&blinn_phong, angle) - expected).abs() < EPSILON);
+            assert!((get_specular(&phong, -angle) - get_specular(&phong, angle)).abs() < EPSILON);
+        }
+        // Lambert has no highlights at all
+        let lambert = |camera_pt| shade(&Lambert, &scene, camera_pt, &material);
+        assert_eq!(get_specular(&lambert, 0.0), 0.0);
+    }
+}
@@ -0,0 +1,286 @@
+use std::path::Path;
+
+use crate::scene::color::Color;
+
+/// What happens to texture coordinates outside of [0; 1]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WrapMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FilterMode {
+    Nearest,
+    /// Blends the four nearest texels of the full resolution image
+    Bilinear,
+    /// Bilinear on the two mip levels closest to the footprint, blended
+    Trilinear,
+}
+
+// One level of the mip chain, each half the size of the previous one
+struct MipLevel {
+    width: usize,
+    height: usize,
+    // Rows from the top of the image, linear light
+    texels: Vec<Color>,
+}
+
+/// Image sampled by the materials, kept in linear light with a full mip chain
+pub struct Texture {
+    levels: Vec<MipLevel>,
+    wrap_mode: WrapMode,
+    filter_mode: FilterMode,
+}
+
+impl Texture {
+    /// Loads a color image, e.g. a diffuse map, decoding it from sRGB
+    pub fn open<Q: AsRef<Path>>(path: Q) -> image::ImageResult<Self> {
+        let img = image::open(path)?.to_rgb8();
+        let (width, height) = img.dimensions();
+        let texels = img.pixels().map(|x| Color::from_srgb8(x.0)).collect();
+        Ok(Texture::from_texels(width as usize, height as usize, texels))
+    }
+
+    /// Loads an image holding data rather than colors, e.g. a normal map, without any decoding
+    pub fn open_linear<Q: AsRef<Path>>(path: Q) -> image::ImageResult<Self> {
+        let img = image::open(path)?.to_rgb8();
+        let (width, height) = img.dimensions();
+        let texels = img
+            .pixels()
+            .map(|x| Color::new(x.0[0] as f32, x.0[1] as f32, x.0[2] as f32) / u8::MAX as f32)
+            .collect();
+        Ok(Texture::from_texels(width as usize, height as usize, texels))
+    }
+
+    /// Rows go from the top of the image
+    pub fn from_texels(width: usize, height: usize, texels: Vec<Color>) -> Self {
+        assert_eq!(width * height, texels.len());
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            texels,
+        }];
+        while let Some(level) = levels.last().unwrap().downsample() {
+            levels.push(level);
+        }
+        Texture {
+            levels,
+            wrap_mode: WrapMode::Repeat,
+            filter_mode: FilterMode::Trilinear,
+        }
+    }
+
+    pub fn wrap_mode(mut self, wrap_mode: WrapMode) -> Self {
+        self.wrap_mode = wrap_mode;
+        self
+    }
+    pub fn filter_mode(mut self, filter_mode: FilterMode) -> Self {
+        self.filter_mode = filter_mode;
+        self
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.levels[0].width
+    }
+    pub fn get_height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    /// Color at the texture coordinates, (0, 0) being the bottom left corner of the image.
+    /// footprint is the size of the sampled area in texture coordinates, it picks the mip level.
+    pub fn sample(&self, tex_coords: [f32; 2], footprint: f32) -> Color {
+        let [u, v] = tex_coords;
+        match self.filter_mode {
+            FilterMode::Nearest => self.levels[0].get_nearest(u, v, self.wrap_mode),
+            FilterMode::Bilinear => self.levels[0].get_bilinear(u, v, self.wrap_mode),
+            FilterMode::Trilinear => {
+                let texels = footprint * self.get_width().max(self.get_height()) as f32;
+                let lod = texels.max(1.0).log2().min((self.levels.len() - 1) as f32);
+                let lower = lod.floor() as usize;
+                let upper = (lower + 1).min(self.levels.len() - 1);
+                let t = lod - lower as f32;
+                let a = self.levels[lower].get_bilinear(u, v, self.wrap_mode);
+                let b = self.levels[upper].get_bilinear(u, v, self.wrap_mode);
+                a * (1.0 - t) + b * t
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Texture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Texture {}x{} ({} levels, {:?}, {:?})",
+            self.get_width(),
+            self.get_height(),
+            self.levels.len(),
+            self.wrap_mode,
+            self.filter_mode
+        )
+    }
+}
+
+impl MipLevel {
+    // Box filtered to half the size, None once down to a single texel
+    fn downsample(&self) -> Option<MipLevel> {
+        if self.width == 1 && self.height == 1 {
+            return None;
+        }
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                // Odd sizes leave the last row or column out of the pairs
+                let x0 = (2 * x).min(self.width - 1);
+                let x1 = (2 * x + 1).min(self.width - 1);
+                let y0 = (2 * y).min(self.height - 1);
+                let y1 = (2 * y + 1).min(self.height - 1);
+                let sum = self.get_texel(x0, y0)
+                    + self.get_texel(x1, y0)
+                    + self.get_texel(x0, y1)
+                    + self.get_texel(x1, y1);
+                texels.push(sum / 4.0);
+            }
+        }
+        Some(MipLevel {
+            width,
+            height,
+            texels,
+        })
+    }
+
+    fn get_texel(&self, x: usize, y: usize) -> Color {
+        self.texels[y * self.width + x]
+    }
+
+    // Texel at integer coordinates which may be out of the image
+    fn get_wrapped(&self, x: i64, y: i64, wrap_mode: WrapMode) -> Color {
+        let wrap = |i: i64, size: usize| -> usize {
+            let size = size as i64;
+            let i = match wrap_mode {
+                WrapMode::Repeat => i.rem_euclid(size),
+                WrapMode::MirroredRepeat => {
+                    let i = i.rem_euclid(2 * size);
+                    if i < size {
+                        i
+                    } else {
+                        2 * size - 1 - i
+                    }
+                }
+                WrapMode::ClampToEdge => i.clamp(0, size - 1),
+            };
+            i as usize
+        };
+        self.get_texel(wrap(x, self.width), wrap(y, self.height))
+    }
+
+    // Continuous texel coordinates, rows counted from the top
+    fn to_texel_space(&self, u: f32, v: f32) -> (f32, f32) {
+        (u * self.width as f32, (1.0 - v) * self.height as f32)
+    }
+
+    fn get_nearest(&self, u: f32, v: f32, wrap_mode: WrapMode) -> Color {
+        let (x, y) = self.to_texel_space(u, v);
+        self.get_wrapped(x.floor() as i64, y.floor() as i64, wrap_mode)
+    }
+
+    fn get_bilinear(&self, u: f32, v: f32, wrap_mode: WrapMode) -> Color {
+        // Texel centers are at the halves
+        let (x, y) = self.to_texel_space(u, v);
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self.get_wrapped(x0, y0, wrap_mode) * (1.0 - tx)
+            + self.get_wrapped(x0 + 1, y0, wrap_mode) * tx;
+        let bottom = self.get_wrapped(x0, y0 + 1, wrap_mode) * (1.0 - tx)
+            + self.get_wrapped(x0 + 1, y0 + 1, wrap_mode) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Single row of texels 0, 1, 2, 3 from left to right
+    fn get_row_texture() -> Texture {
+        Texture::from_texels(4, 1, (0..4).map(|x| Color::grey(x as f32)).collect())
+    }
+
+    // Every texel different, so that the mip levels all differ too
+    fn get_gradient_texture(size: usize) -> Texture {
+        let texels = (0..size * size).map(|i| Color::new(i as f32, (i % size) as f32, 1.0));
+        Texture::from_texels(size, size, texels.collect())
+    }
+
+    #[test]
+    fn t_mip_chain() {
+        let texture = Texture::from_texels(4, 2, (0..8).map(|x| Color::grey(x as f32)).collect());
+        let sizes: Vec<_> = texture.levels.iter().map(|x| (x.width, x.height)).collect();
+        assert_eq!(sizes, vec![(4, 2), (2, 1), (1, 1)]);
+        // Box filtered, rows 0 1 2 3 and 4 5 6 7
+        assert_eq!(texture.levels[1].texels, vec![Color::grey(2.5), Color::grey(4.5)]);
+        assert_eq!(texture.levels[2].texels, vec![Color::grey(3.5)]);
+    }
+
+    #[test]
+    fn t_wrap_modes() {
+        let sample = |wrap_mode, u| {
+            let texture = get_row_texture().filter_mode(FilterMode::Nearest).wrap_mode(wrap_mode);
+            texture.sample([u, 0.5], 0.0).r
+        };
+        // Just off the left edge, then just off the right one
+        assert_eq!(sample(WrapMode::Repeat, -0.1), 3.0);
+        assert_eq!(sample(WrapMode::Repeat, 1.1), 0.0);
+        assert_eq!(sample(WrapMode::Repeat, 2.6), 2.0);
+        assert_eq!(sample(WrapMode::MirroredRepeat, -0.1), 0.0);
+        assert_eq!(sample(WrapMode::MirroredRepeat, 1.1), 3.0);
+        assert_eq!(sample(WrapMode::MirroredRepeat, 1.6), 1.0);
+        assert_eq!(sample(WrapMode::ClampToEdge, -0.1), 0.0);
+        assert_eq!(sample(WrapMode::ClampToEdge, 1.1), 3.0);
+        assert_eq!(sample(WrapMode::ClampToEdge, -5.0), 0.0);
+    }
+
+    #[test]
+    fn t_bilinear() {
+        let texture = get_row_texture().filter_mode(FilterMode::Bilinear);
+        // Texel centers are at 1/8, 3/8, ...
+        assert_eq!(texture.sample([0.375, 0.5], 0.0).r, 1.0);
+        assert_eq!(texture.sample([0.5, 0.5], 0.0).r, 1.5);
+        assert_eq!(texture.sample([0.4375, 0.5], 0.0).r, 1.25);
+        // Half way between the last and the first texel across the edge
+        assert_eq!(texture.sample([0.0, 0.5], 0.0).r, 1.5);
+        let texture = texture.wrap_mode(WrapMode::ClampToEdge);
+        assert_eq!(texture.sample([0.0, 0.5], 0.0).r, 0.0);
+        assert_eq!(texture.sample([1.0, 0.5], 0.0).r, 3.0);
+    }
+
+    #[test]
+    fn t_mip_level_selection() {
+        let texture = get_gradient_texture(8);
+        let tex_coords = [0.3, 0.6];
+        let get_level = |level: usize| {
+            texture.levels[level].get_bilinear(tex_coords[0], tex_coords[1], WrapMode::Repeat)
+        };
+
+        // Footprints of a texel or less use the full resolution
+        assert_eq!(texture.sample(tex_coords, 0.0), get_level(0));
+        assert_eq!(texture.sample(tex_coords, 1.0 / 8.0), get_level(0));
+        // Two texels wide is the second level, four the third one
+        assert_eq!(texture.sample(tex_coords, 2.0 / 8.0), get_level(1));
+        assert_eq!(texture.sample(tex_coords, 4.0 / 8.0), get_level(2));
+        // In between the two levels are blended
+        let blended = texture.sample(tex_coords, 3.0 / 8.0);
+        let t = 3.0_f32.log2() - 1.0;
+        let expected = get_level(1) * (1.0 - t) + get_level(2) * t;
+        assert!((blended.r - expected.r).abs() < 1e-4 && (blended.g - expected.g).abs() < 1e-4);
+        // Way past the size of the image it is the average of all the texels
+        assert_eq!(texture.sample(tex_coords, 100.0), get_level(3));
+        assert_eq!(get_level(3), Color::new(31.5, 3.5, 1.0));
+    }
+}
@@ -1,28 +1,39 @@
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
+use std::path::Path;
 use std::sync::Arc;
 use wavefront_obj::obj::{self, ObjSet};
 use wavefront_obj::ParseError;
 
-use geometry::triangle::Triangle;
-use geometry::{Point3d, PrimitiveType};
+use geometry::triangle::{generate_vertex_normals, generate_vertex_tangents, Triangle};
+use geometry::{Point3d, PrimitiveType, Vector3d};
 use crate::scene::IntoPrimitives;
 
+// Faces meeting at a sharper angle keep a hard edge between them when normals get generated
+const CREASE_ANGLE: f32 = 60.0;
+
 pub struct WfObj {
     model: ObjSet,
 }
 
 impl WfObj {
     pub fn new(path: &str) -> Self {
+        WfObj::open(path).unwrap()
+    }
+
+    /// Same as new() but tells what went wrong, malformed files give InvalidData errors
+    pub fn open<Q: AsRef<Path>>(path: Q) -> io::Result<Self> {
         let file_content = {
-            let mut f = File::open(path).unwrap();
+            let mut f = File::open(path)?;
             let mut content = String::new();
-            f.read_to_string(&mut content).unwrap();
+            f.read_to_string(&mut content)?;
             content
         };
-        WfObj {
-            model: obj::parse(file_content).unwrap(),
-        }
+        let model = obj::parse(file_content).map_err(|e: ParseError| {
+            let message = format!("line {}: {}", e.line_number, e.message);
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })?;
+        Ok(WfObj { model })
     }
 
     fn iter(&self) -> IterWfObj {
@@ -37,7 +48,11 @@ impl WfObj {
 
 impl IntoPrimitives for WfObj {
     fn to_primitives(&self) -> Vec<PrimitiveType> {
-        self.iter().collect()
+        // Faces without vn records get smooth normals anyway
+        let mut triangles: Vec<Triangle> = self.iter().collect();
+        generate_vertex_normals(&mut triangles, CREASE_ANGLE);
+        generate_vertex_tangents(&mut triangles);
+        triangles.into_iter().map(PrimitiveType::Triangle).collect()
     }
 }
 pub struct IterWfObj<'a> {
@@ -48,20 +63,24 @@ pub struct IterWfObj<'a> {
 }
 
 impl<'a> Iterator for IterWfObj<'a> {
-    type Item = PrimitiveType;
+    type Item = Triangle;
     fn next(&mut self) -> Option<Self::Item> {
         let object = self.wfobj.model.objects.get(self.oidx)?;
         let geometry = object.geometry.get(self.gidx)?;
         let shape = geometry.shapes.get(self.sidx)?;
 
-        let coord_idx = match shape.primitive {
+        let (coord_idx, tex_idx, normal_idx) = match shape.primitive {
             obj::Primitive::Triangle(
-                (coord_a, _a1, _a2),
-                (coord_b, _b1, _b2),
-                (coord_c, _c1, _c2),
+                (coord_a, tex_a, normal_a),
+                (coord_b, tex_b, normal_b),
+                (coord_c, tex_c, normal_c),
             ) => {
                 //println!("IterObjSet {}:{}:{}", coord_a, coord_b, coord_c);
-                Some((coord_a, coord_b, coord_c))
+                Some((
+                    (coord_a, coord_b, coord_c),
+                    (tex_a, tex_b, tex_c),
+                    (normal_a, normal_b, normal_c),
+                ))
             }
             _ => {
                 //println!("Unsupported primitive!");
@@ -83,10 +102,72 @@ impl<'a> Iterator for IterWfObj<'a> {
             self.oidx += 1;
         }
 
-        Some(PrimitiveType::Triangle(Triangle::new(
+        let mut triangle = Triangle::new(
             Point3d::from_coords(a.x as f32, a.y as f32, a.z as f32),
             Point3d::from_coords(b.x as f32, b.y as f32, b.z as f32),
             Point3d::from_coords(c.x as f32, c.y as f32, c.z as f32),
-        )))
+        );
+
+        let get_tex_coords = |idx: usize| {
+            let t = object.tex_vertices[idx];
+            [t.u as f32, t.v as f32]
+        };
+        if let (Some(ta), Some(tb), Some(tc)) = tex_idx {
+            triangle = triangle.with_tex_coords([
+                get_tex_coords(ta),
+                get_tex_coords(tb),
+                get_tex_coords(tc),
+            ]);
+        }
+
+        let get_normal = |idx: usize| {
+            let n = object.normals[idx];
+            Vector3d::from_coords(n.x as f32, n.y as f32, n.z as f32).normalize()
+        };
+        match normal_idx {
+            (Some(na), Some(nb), Some(nc)) => Some(triangle.with_vertex_normals([
+                get_normal(na),
+                get_normal(nb),
+                get_normal(nc),
+            ])),
+            _ => Some(triangle),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two triangles folded along their shared edge, the first one with vn records pointing
+    // straight up
+    const FOLD: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nvn 0 0 1\n\
+                        f 1//1 2//1 3//1\nf 1 3 4\n";
+
+    #[test]
+    fn t_obj_normals() {
+        let path = std::env::temp_dir().join(format!("pixodel_{}_fold.obj", std::process::id()));
+        std::fs::write(&path, FOLD).unwrap();
+        let model = WfObj::open(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let triangles: Vec<Triangle> = model
+            .unwrap()
+            .to_primitives()
+            .into_iter()
+            .map(|p| match p {
+                PrimitiveType::Triangle(t) => t,
+                _ => panic!("not a triangle"),
+            })
+            .collect();
+        assert_eq!(triangles.len(), 2);
+        // Given normals are kept, not averaged with the other face
+        for n in triangles[0].vertex_normals.unwrap() {
+            assert_eq!((n.x, n.y, n.z), (0.0, 0.0, 1.0));
+        }
+        // The faces meet at a right angle, sharper than the crease angle
+        for n in triangles[1].vertex_normals.unwrap() {
+            assert_eq!((n.x, n.y, n.z), (1.0, 0.0, 0.0));
+        }
     }
 }
@@ -0,0 +1,985 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use geometry::sphere::Sphere;
+use geometry::triangle::Triangle;
+use geometry::{Point3d, Vector3d};
+use serde::Deserialize;
+
+use crate::antialiasing::{PixelFilter, SamplePattern, Supersampler};
+use crate::camera::{
+    Camera, EquirectangularCamera, FisheyeCamera, OrthographicCamera, PerspectiveCamera,
+};
+use crate::framebuffer::ToneMapping;
+use crate::scene::shading::{BlinnPhong, CookTorrance, Lambert, Phong};
+use crate::scene::{
+    Color, IntoPrimitives, Light, Material, Scene, SceneObj, Shader, SphereObj, Texture, TriObj,
+    WfObj,
+};
+
+/// Everything needed to render an image, read from a TOML scene file:
+///
+/// ```toml
+/// background = [0.01, 0.01, 0.01]
+///
+/// [render]
+/// width = 1280
+/// height = 720
+/// output = "bunny.png"
+///
+/// [camera]
+/// type = "perspective"
+/// eye = [0.0, 0.0, 0.0]
+/// target = [0.0, 0.0, -1.0]
+///
+/// [[objects]]
+/// type = "mesh"
+/// path = "../models/bunny.obj"
+/// scale = [7.0, 7.0, 7.0]
+/// translate = [5.0, -8.0, -50.0]
+/// material = { albedo = [0.8, 0.6, 0.4] }
+///
+/// [[lights]]
+/// type = "point"
+/// position = [1.0, 0.0, 10.0]
+/// intensity = 3600.0
+/// ```
+///
+/// Colors are linear RGB, angles in degrees. Meshes and textures are looked up relative to the
+/// scene file, the outputs relative to the working directory. See `pixodel/scenes` for all the
+/// options.
+pub struct SceneFile {
+    pub scene: Scene,
+    pub camera: Box<dyn Camera>,
+    pub settings: RenderSettings,
+}
+
+/// How the image gets rendered and where it goes
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub output: PathBuf,
+    // Unclipped radiance for grading, OpenEXR and Radiance respectively, not saved if None
+    pub exr_output: Option<PathBuf>,
+    pub hdr_output: Option<PathBuf>,
+    pub tone_mapping: ToneMapping,
+    // In stops, applied before the tone mapping
+    pub exposure: f32,
+    // 0 - Whitted-style ray casting, otherwise the number of path traced samples per pixel
+    pub path_tracing_samples: u32,
+    // Maximum number of reflections traced after the primary ray when ray casting
+    pub recursion_depth: usize,
+    pub shader: Box<dyn Shader>,
+    // Ray casting only, the path traced passes take one uniformly placed sample per pixel each
+    pub supersampler: Supersampler,
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    /// The scene file or a mesh could not be read
+    Io(PathBuf, std::io::Error),
+    Parse(toml::de::Error),
+    Texture(PathBuf, image::ImageError),
+    /// A setting out of its range
+    Invalid(String),
+}
+
+impl Display for SceneFileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneFileError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            SceneFileError::Parse(e) => write!(f, "{}", e),
+            SceneFileError::Texture(path, e) => write!(f, "{}: {}", path.display(), e),
+            SceneFileError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+impl SceneFile {
+    pub fn load<Q: AsRef<Path>>(path: Q) -> Result<SceneFile, SceneFileError> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).map_err(|e| SceneFileError::Io(path.to_path_buf(), e))?;
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        SceneFile::from_str(&text, base_dir)
+    }
+
+    /// Parses the contents of a scene file, base_dir is where the relative paths start from
+    pub fn from_str(text: &str, base_dir: &Path) -> Result<SceneFile, SceneFileError> {
+        let desc: SceneDesc = toml::from_str(text).map_err(SceneFileError::Parse)?;
+        Loader::new(base_dir).load(desc)
+    }
+}
+
+// What the file holds, mirroring the types it gets turned into. Unknown fields are rejected so
+// that misspelled options do not go unnoticed, except next to the flattened enums where serde
+// cannot tell them apart.
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    render: RenderDesc,
+    camera: CameraDesc,
+    background: Option<[f32; 3]>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RenderDesc {
+    width: u32,
+    height: u32,
+    output: PathBuf,
+    exr_output: Option<PathBuf>,
+    hdr_output: Option<PathBuf>,
+    #[serde(default)]
+    tone_mapping: ToneMappingDesc,
+    #[serde(default)]
+    exposure: f32,
+    #[serde(default)]
+    path_tracing_samples: u32,
+    #[serde(default = "default_recursion_depth")]
+    recursion_depth: usize,
+    #[serde(default)]
+    shader: ShaderDesc,
+    // Supersampling when ray casting, the defaults of Supersampler::new() if left out
+    pattern: Option<PatternDesc>,
+    filter: Option<FilterDesc>,
+}
+
+fn default_recursion_depth() -> usize {
+    4
+}
+
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum ToneMappingDesc {
+    Clip,
+    Exposure,
+    Reinhard,
+    #[default]
+    AcesFilmic,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum ShaderDesc {
+    Lambert,
+    Phong,
+    #[default]
+    BlinnPhong,
+    CookTorrance,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum PatternDesc {
+    Stratified {
+        n: usize,
+    },
+    Jittered {
+        n: usize,
+    },
+    Halton {
+        num_samples: usize,
+    },
+    Sobol {
+        num_samples: usize,
+    },
+    Adaptive {
+        min_samples: usize,
+        max_samples: usize,
+        max_error: f32,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum FilterDesc {
+    Box {
+        radius: f32,
+    },
+    Tent {
+        radius: f32,
+    },
+    Gaussian {
+        radius: f32,
+        alpha: f32,
+    },
+    // B and C default to 1/3
+    MitchellNetravali {
+        radius: f32,
+        b: Option<f32>,
+        c: Option<f32>,
+    },
+}
+
+#[derive(Deserialize)]
+struct CameraDesc {
+    #[serde(flatten)]
+    projection: ProjectionDesc,
+    eye: [f32; 3],
+    target: [f32; 3],
+    #[serde(default = "default_up")]
+    up: [f32; 3],
+    aspect: Option<f32>,
+    // [near, far]
+    clip: Option<[f32; 2]>,
+    // [open, close]
+    shutter: Option<[f32; 2]>,
+}
+
+fn default_up() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ProjectionDesc {
+    Perspective {
+        fov: Option<f32>,
+        aperture: Option<f32>,
+        focus_distance: Option<f32>,
+        aperture_blades: Option<u32>,
+    },
+    Orthographic {
+        view_height: f32,
+    },
+    Fisheye {
+        fov: Option<f32>,
+    },
+    Equirectangular,
+}
+
+#[derive(Deserialize)]
+struct ObjectDesc {
+    #[serde(flatten)]
+    shape: ShapeDesc,
+    #[serde(default = "default_scale")]
+    scale: [f32; 3],
+    #[serde(default)]
+    rotate: [f32; 3],
+    #[serde(default)]
+    translate: [f32; 3],
+    end_scale: Option<[f32; 3]>,
+    end_rotate: Option<[f32; 3]>,
+    end_translate: Option<[f32; 3]>,
+    #[serde(default)]
+    material: MaterialDesc,
+}
+
+fn default_scale() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ShapeDesc {
+    Mesh { path: PathBuf },
+    Sphere { center: [f32; 3], radius: f32 },
+    Triangle { vertices: [[f32; 3]; 3] },
+}
+
+// Left out properties keep the defaults of Material::new()
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    albedo: Option<[f32; 3]>,
+    ambient_reflection: Option<f32>,
+    diffuse_reflection: Option<f32>,
+    specular_reflection: Option<f32>,
+    shininess: Option<f32>,
+    reflectivity: Option<f32>,
+    emission: Option<[f32; 3]>,
+    refraction_index: Option<f32>,
+    // Either one makes it a metallic-roughness material
+    metallic: Option<f32>,
+    roughness: Option<f32>,
+    diffuse_texture: Option<PathBuf>,
+    specular_texture: Option<PathBuf>,
+    normal_texture: Option<PathBuf>,
+    bump_texture: Option<PathBuf>,
+    bump_scale: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct LightDesc {
+    #[serde(flatten)]
+    kind: LightKindDesc,
+    intensity: f32,
+    color: Option<[f32; 3]>,
+    samples: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LightKindDesc {
+    Point {
+        position: [f32; 3],
+    },
+    Directional {
+        direction: [f32; 3],
+    },
+    Spot {
+        position: [f32; 3],
+        direction: [f32; 3],
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    Rectangle {
+        corner: [f32; 3],
+        edge_u: [f32; 3],
+        edge_v: [f32; 3],
+    },
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+    },
+}
+
+// Turns the descriptions into the scene, loading every mesh and texture only once however many
+// objects use it
+struct Loader<'a> {
+    base_dir: &'a Path,
+    meshes: HashMap<PathBuf, Arc<WfObj>>,
+    textures: HashMap<(PathBuf, bool), Arc<Texture>>,
+}
+
+impl<'a> Loader<'a> {
+    fn new(base_dir: &'a Path) -> Self {
+        Loader {
+            base_dir,
+            meshes: HashMap::new(),
+            textures: HashMap::new(),
+        }
+    }
+
+    fn load(mut self, desc: SceneDesc) -> Result<SceneFile, SceneFileError> {
+        check_render_desc(&desc.render)?;
+        let settings = get_render_settings(&desc.render);
+        let camera = get_camera(&desc.camera, settings.width, settings.height);
+
+        let mut scene = Scene::new().pixel_spread_angle(camera.get_pixel_spread_angle());
+        if let Some(background) = desc.background {
+            scene = scene.background(get_color(background));
+        }
+        for obj in &desc.objects {
+            scene = scene.add_obj(self.get_object(obj)?);
+        }
+        for light in &desc.lights {
+            scene = scene.add_light(get_light(light));
+        }
+
+        Ok(SceneFile {
+            scene,
+            camera,
+            settings,
+        })
+    }
+
+    fn get_object(&mut self, desc: &ObjectDesc) -> Result<SceneObj, SceneFileError> {
+        let object: Arc<dyn IntoPrimitives + Send + Sync> = match &desc.shape {
+            ShapeDesc::Mesh { path } => self.get_mesh(path)?,
+            ShapeDesc::Sphere { center, radius } => {
+                let is_uniform = |[x, y, z]: [f32; 3]| x == y && y == z;
+                if !is_uniform(desc.scale) || !desc.end_scale.iter().all(|&s| is_uniform(s)) {
+                    let message = "objects: spheres can only be scaled uniformly";
+                    return Err(SceneFileError::Invalid(message.into()));
+                }
+                Arc::new(SphereObj::new(Sphere::new(get_point(*center), *radius)))
+            }
+            ShapeDesc::Triangle { vertices } => Arc::new(TriObj::new(Triangle::new(
+                get_point(vertices[0]),
+                get_point(vertices[1]),
+                get_point(vertices[2]),
+            ))),
+        };
+
+        let [sx, sy, sz] = desc.scale;
+        let [rx, ry, rz] = desc.rotate;
+        let [tx, ty, tz] = desc.translate;
+        let mut obj = SceneObj::new(object)
+            .scale(sx, sy, sz)
+            .rotate(rx, ry, rz)
+            .translate(tx, ty, tz)
+            .material(self.get_material(&desc.material)?);
+        if let Some([x, y, z]) = desc.end_scale {
+            obj = obj.end_scale(x, y, z);
+        }
+        if let Some([x, y, z]) = desc.end_rotate {
+            obj = obj.end_rotate(x, y, z);
+        }
+        if let Some([x, y, z]) = desc.end_translate {
+            obj = obj.end_translate(x, y, z);
+        }
+        Ok(obj)
+    }
+
+    fn get_material(&mut self, desc: &MaterialDesc) -> Result<Material, SceneFileError> {
+        let mut material = Material::new();
+        if let Some(albedo) = desc.albedo {
+            material = material.albedo(get_color(albedo));
+        }
+        if let Some(ambient_reflection) = desc.ambient_reflection {
+            material = material.ambient_reflection(ambient_reflection);
+        }
+        if let Some(diffuse_reflection) = desc.diffuse_reflection {
+            material = material.diffuse_reflection(diffuse_reflection);
+        }
+        if let Some(specular_reflection) = desc.specular_reflection {
+            material = material.specular_reflection(specular_reflection);
+        }
+        if let Some(shininess) = desc.shininess {
+            material = material.shininess(shininess);
+        }
+        if let Some(reflectivity) = desc.reflectivity {
+            material = material.reflectivity(reflectivity);
+        }
+        if let Some(emission) = desc.emission {
+            material = material.emission(get_color(emission));
+        }
+        if let Some(refraction_index) = desc.refraction_index {
+            material = material.dielectric(refraction_index);
+        }
+        if desc.metallic.is_some() || desc.roughness.is_some() {
+            let metallic = desc.metallic.unwrap_or(0.0);
+            let roughness = desc.roughness.unwrap_or(0.5);
+            material = material.metallic_roughness(metallic, roughness);
+        }
+
+        // Only the diffuse texture holds colors, the rest is data
+        if let Some(path) = &desc.diffuse_texture {
+            material = material.diffuse_texture(self.get_texture(path, true)?);
+        }
+        if let Some(path) = &desc.specular_texture {
+            material = material.specular_texture(self.get_texture(path, false)?);
+        }
+        if let Some(path) = &desc.normal_texture {
+            material = material.normal_texture(self.get_texture(path, false)?);
+        }
+        if let Some(path) = &desc.bump_texture {
+            let bump_scale = desc.bump_scale.unwrap_or(material.bump_scale);
+            material = material.bump_texture(self.get_texture(path, false)?, bump_scale);
+        }
+        Ok(material)
+    }
+
+    fn get_mesh(&mut self, path: &Path) -> Result<Arc<WfObj>, SceneFileError> {
+        let path = self.base_dir.join(path);
+        if let Some(mesh) = self.meshes.get(&path) {
+            return Ok(mesh.clone());
+        }
+        let mesh = Arc::new(WfObj::open(&path).map_err(|e| SceneFileError::Io(path.clone(), e))?);
+        self.meshes.insert(path, mesh.clone());
+        Ok(mesh)
+    }
+
+    fn get_texture(&mut self, path: &Path, srgb: bool) -> Result<Arc<Texture>, SceneFileError> {
+        let path = self.base_dir.join(path);
+        let key = (path, srgb);
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(texture.clone());
+        }
+        let texture = if srgb {
+            Texture::open(&key.0)
+        } else {
+            Texture::open_linear(&key.0)
+        };
+        let texture = Arc::new(texture.map_err(|e| SceneFileError::Texture(key.0.clone(), e))?);
+        self.textures.insert(key, texture.clone());
+        Ok(texture)
+    }
+}
+
+fn check_render_desc(desc: &RenderDesc) -> Result<(), SceneFileError> {
+    if desc.width == 0 || desc.height == 0 {
+        let size = format!("{} x {}", desc.width, desc.height);
+        return Err(SceneFileError::Invalid(format!("render: empty image size {}", size)));
+    }
+    let num_samples = match desc.pattern {
+        Some(PatternDesc::Stratified { n } | PatternDesc::Jittered { n }) => n,
+        Some(PatternDesc::Halton { num_samples } | PatternDesc::Sobol { num_samples }) => {
+            num_samples
+        }
+        Some(PatternDesc::Adaptive {
+            min_samples,
+            max_samples,
+            ..
+        }) => min_samples.min(max_samples),
+        None => 1,
+    };
+    if num_samples == 0 {
+        return Err(SceneFileError::Invalid("render: pattern without samples".into()));
+    }
+    let radius = match desc.filter {
+        Some(
+            FilterDesc::Box { radius }
+            | FilterDesc::Tent { radius }
+            | FilterDesc::Gaussian { radius, .. }
+            | FilterDesc::MitchellNetravali { radius, .. },
+        ) => radius,
+        None => 1.0,
+    };
+    if radius <= 0.0 {
+        return Err(SceneFileError::Invalid(format!("render: filter radius {}", radius)));
+    }
+    Ok(())
+}
+
+fn get_render_settings(desc: &RenderDesc) -> RenderSettings {
+    let shader: Box<dyn Shader> = match desc.shader {
+        ShaderDesc::Lambert => Box::new(Lambert),
+        ShaderDesc::Phong => Box::new(Phong),
+        ShaderDesc::BlinnPhong => Box::new(BlinnPhong),
+        ShaderDesc::CookTorrance => Box::new(CookTorrance),
+    };
+    let mut supersampler = Supersampler::new();
+    if let Some(pattern) = &desc.pattern {
+        supersampler = supersampler.pattern(get_sample_pattern(pattern));
+    }
+    if let Some(filter) = &desc.filter {
+        supersampler = supersampler.filter(get_pixel_filter(filter));
+    }
+    RenderSettings {
+        width: desc.width,
+        height: desc.height,
+        output: desc.output.clone(),
+        exr_output: desc.exr_output.clone(),
+        hdr_output: desc.hdr_output.clone(),
+        tone_mapping: match desc.tone_mapping {
+            ToneMappingDesc::Clip => ToneMapping::Clip,
+            ToneMappingDesc::Exposure => ToneMapping::Exposure,
+            ToneMappingDesc::Reinhard => ToneMapping::Reinhard,
+            ToneMappingDesc::AcesFilmic => ToneMapping::AcesFilmic,
+        },
+        exposure: desc.exposure,
+        path_tracing_samples: desc.path_tracing_samples,
+        recursion_depth: desc.recursion_depth,
+        shader,
+        supersampler,
+    }
+}
+
+fn get_sample_pattern(desc: &PatternDesc) -> SamplePattern {
+    match *desc {
+        PatternDesc::Stratified { n } => SamplePattern::Stratified { n },
+        PatternDesc::Jittered { n } => SamplePattern::Jittered { n },
+        PatternDesc::Halton { num_samples } => SamplePattern::Halton { num_samples },
+        PatternDesc::Sobol { num_samples } => SamplePattern::Sobol { num_samples },
+        PatternDesc::Adaptive {
+            min_samples,
+            max_samples,
+            max_error,
+        } => SamplePattern::Adaptive {
+            min_samples,
+            max_samples,
+            max_error,
+        },
+    }
+}
+
+fn get_pixel_filter(desc: &FilterDesc) -> PixelFilter {
+    match *desc {
+        FilterDesc::Box { radius } => PixelFilter::Box { radius },
+        FilterDesc::Tent { radius } => PixelFilter::Tent { radius },
+        FilterDesc::Gaussian { radius, alpha } => PixelFilter::Gaussian { radius, alpha },
+        FilterDesc::MitchellNetravali { radius, b, c } => PixelFilter::MitchellNetravali {
+            radius,
+            b: b.unwrap_or(1.0 / 3.0),
+            c: c.unwrap_or(1.0 / 3.0),
+        },
+    }
+}
+
+fn get_camera(desc: &CameraDesc, width: u32, height: u32) -> Box<dyn Camera> {
+    let (eye, target, up) = (
+        get_point(desc.eye),
+        get_point(desc.target),
+        get_vector(desc.up),
+    );
+    let aspect = desc.aspect.unwrap_or(width as f32 / height as f32);
+    let [near, far] = desc.clip.unwrap_or([0.0, f32::MAX]);
+    let [open, close] = desc.shutter.unwrap_or([0.0, 0.0]);
+
+    match desc.projection {
+        ProjectionDesc::Perspective {
+            fov,
+            aperture,
+            focus_distance,
+            aperture_blades,
+        } => {
+            let mut camera = PerspectiveCamera::new(width, height)
+                .look_at(eye, target, up)
+                .aspect(aspect)
+                .clip(near, far)
+                .shutter(open, close);
+            if let Some(fov) = fov {
+                camera = camera.fov(fov);
+            }
+            if let Some(aperture) = aperture {
+                // In focus at the target unless told otherwise
+                let focus_distance = focus_distance.unwrap_or((target - eye).len());
+                camera = camera.aperture(aperture).focus_distance(focus_distance);
+            }
+            if let Some(aperture_blades) = aperture_blades {
+                camera = camera.aperture_blades(aperture_blades);
+            }
+            Box::new(camera)
+        }
+        ProjectionDesc::Orthographic { view_height } => Box::new(
+            OrthographicCamera::new(width, height)
+                .look_at(eye, target, up)
+                .view_height(view_height)
+                .aspect(aspect)
+                .clip(near, far)
+                .shutter(open, close),
+        ),
+        ProjectionDesc::Fisheye { fov } => {
+            let mut camera = FisheyeCamera::new(width, height)
+                .look_at(eye, target, up)
+                .aspect(aspect)
+                .clip(near, far)
+                .shutter(open, close);
+            if let Some(fov) = fov {
+                camera = camera.fov(fov);
+            }
+            Box::new(camera)
+        }
+        ProjectionDesc::Equirectangular => Box::new(
+            EquirectangularCamera::new(width, height)
+                .look_at(eye, target, up)
+                .clip(near, far)
+                .shutter(open, close),
+        ),
+    }
+}
+
+fn get_light(desc: &LightDesc) -> Light {
+    let intensity = desc.intensity;
+    let mut light = match desc.kind {
+        LightKindDesc::Point { position } => Light::point(get_point(position), intensity),
+        LightKindDesc::Directional { direction } => {
+            Light::directional(get_vector(direction), intensity)
+        }
+        LightKindDesc::Spot {
+            position,
+            direction,
+            inner_angle,
+            outer_angle,
+        } => Light::spot(
+            get_point(position),
+            get_vector(direction),
+            inner_angle,
+            outer_angle,
+            intensity,
+        ),
+        LightKindDesc::Rectangle {
+            corner,
+            edge_u,
+            edge_v,
+        } => Light::rectangle(
+            get_point(corner),
+            get_vector(edge_u),
+            get_vector(edge_v),
+            intensity,
+        ),
+        LightKindDesc::Sphere { center, radius } => {
+            Light::sphere(get_point(center), radius, intensity)
+        }
+    };
+    if let Some(color) = desc.color {
+        light = light.color(get_color(color));
+    }
+    if let Some(samples) = desc.samples {
+        light = light.samples(samples);
+    }
+    light
+}
+
+fn get_point(coords: [f32; 3]) -> Point3d {
+    Point3d::from_coords(coords[0], coords[1], coords[2])
+}
+
+fn get_vector(coords: [f32; 3]) -> Vector3d {
+    Vector3d::from_coords(coords[0], coords[1], coords[2])
+}
+
+fn get_color(rgb: [f32; 3]) -> Color {
+    Color::new(rgb[0], rgb[1], rgb[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::light::LightType;
+    use geometry::Ray3d;
+    use std::f32::consts::PI;
+
+    const RENDER: &str = "width = 2\nheight = 2";
+    const CAMERA: &str = "type = \"perspective\"";
+
+    // Minimal scene around the parts under test, the camera at the origin looking down -Z
+    fn get_scene(render: &str, camera: &str, rest: &str) -> String {
+        format!(
+            "[render]\noutput = \"out.png\"\n{}\n\n\
+             [camera]\neye = [0.0, 0.0, 0.0]\ntarget = [0.0, 0.0, -1.0]\n{}\n\n{}\n",
+            render, camera, rest
+        )
+    }
+
+    fn load(text: &str) -> Result<SceneFile, SceneFileError> {
+        SceneFile::from_str(text, &std::env::temp_dir())
+    }
+
+    fn load_light(light: &str) -> Light {
+        let text = get_scene(RENDER, CAMERA, &format!("[[lights]]\nintensity = 2.0\n{}", light));
+        let scene = load(&text).unwrap().scene;
+        assert_eq!(scene.lights.len(), 1);
+        scene.lights[0]
+    }
+
+    fn assert_direction(ray: &Ray3d, expected: [f32; 3]) {
+        let error = (ray.get_direction().normalize() - get_vector(expected).normalize()).len();
+        assert!(error < 1e-4, "{:?} instead of {:?}", ray.get_direction(), expected);
+    }
+
+    #[test]
+    fn t_bunny() {
+        let mut desc: SceneDesc = toml::from_str(include_str!("../scenes/bunny.toml")).unwrap();
+        assert_eq!(desc.objects.len(), 1);
+        let object = &desc.objects[0];
+        assert!(matches!(&object.shape, ShapeDesc::Mesh { path }
+            if path == Path::new("../models/bunny.obj")));
+        assert_eq!(object.end_rotate, None);
+        assert_eq!(object.material.albedo, Some([1.0, 1.0, 1.0]));
+        // A pinhole with the shutter closed, the way the scene was hardcoded before
+        assert!(matches!(desc.camera.projection,
+            ProjectionDesc::Perspective { aperture: None, fov: Some(fov), .. } if fov == 35.0));
+        assert_eq!(desc.camera.shutter, None);
+        // The mesh is not part of the repository
+        desc.objects.clear();
+
+        let file = Loader::new(Path::new("scenes")).load(desc).unwrap();
+        let settings = &file.settings;
+        assert_eq!((settings.width, settings.height), (1280, 720));
+        assert_eq!(settings.output, PathBuf::from("myimg2.png"));
+        assert_eq!(settings.exr_output, Some(PathBuf::from("myimg2.exr")));
+        assert_eq!(settings.hdr_output, None);
+        assert_eq!(settings.tone_mapping, ToneMapping::AcesFilmic);
+        assert_eq!(settings.path_tracing_samples, 0);
+        assert_eq!(settings.recursion_depth, 4);
+        let pixel_center = Supersampler::new().pattern(SamplePattern::Stratified { n: 1 });
+        assert_eq!(settings.supersampler, pixel_center);
+        assert_eq!(file.scene.lights.len(), 1);
+        let position = Point3d::from_coords(1.0, 0.0, 10.0);
+        assert_eq!(file.scene.lights[0].kind, LightType::Point { position });
+        assert_eq!(file.camera.get_pixel_spread_angle(), 35f32.to_radians() / 720.0);
+    }
+
+    #[test]
+    fn t_bunny_showcases() {
+        let load_showcase = |text: &str| {
+            let mut desc: SceneDesc = toml::from_str(text).unwrap();
+            desc.objects.clear();
+            Loader::new(Path::new("scenes")).load(desc).unwrap()
+        };
+
+        let text = include_str!("../scenes/bunny_dof.toml");
+        let desc: SceneDesc = toml::from_str(text).unwrap();
+        assert!(matches!(desc.camera.projection,
+            ProjectionDesc::Perspective { aperture: Some(a), focus_distance: Some(d), .. }
+            if a > 0.0 && d == 50.0));
+        assert_eq!(desc.camera.shutter, None);
+        assert_eq!(desc.objects[0].end_rotate, None);
+        let file = load_showcase(text);
+        assert_eq!(file.settings.output, PathBuf::from("bunny_dof.png"));
+
+        let text = include_str!("../scenes/bunny_motion_blur.toml");
+        let desc: SceneDesc = toml::from_str(text).unwrap();
+        assert!(matches!(desc.camera.projection,
+            ProjectionDesc::Perspective { aperture: None, .. }));
+        assert_eq!(desc.camera.shutter, Some([0.0, 0.5]));
+        assert_eq!(desc.objects[0].end_rotate, Some([30.0, -47.0, 0.0]));
+        let file = load_showcase(text);
+        assert_eq!(file.settings.output, PathBuf::from("bunny_motion_blur.png"));
+    }
+
+    #[test]
+    fn t_cameras() {
+        let load_camera = |camera: &str| load(&get_scene(RENDER, camera, "")).unwrap().camera;
+        // Through the middle of the image and its left edge
+        let (center, left) = (([0, 1], [1.0, 0.0]), ([0, 0], [0.0, 1.0]));
+
+        let camera = load_camera("type = \"perspective\"\nfov = 90.0\nclip = [1.0, 10.0]");
+        assert_direction(&camera.generate_ray(center.0, center.1), [0.0, 0.0, -1.0]);
+        assert_direction(&camera.generate_ray(left.0, left.1), [-1.0, 0.0, -1.0]);
+        assert_eq!(camera.get_pixel_spread_angle(), PI / 4.0);
+        let ray = camera.generate_ray(center.0, center.1);
+        assert_eq!((ray.get_t_min(), ray.get_t_max()), (1.0, 10.0));
+
+        let camera = load_camera("type = \"orthographic\"\nview_height = 4.0");
+        let ray = camera.generate_ray(left.0, left.1);
+        assert_direction(&ray, [0.0, 0.0, -1.0]);
+        assert_eq!(ray.get_origin(), Point3d::from_coords(-2.0, 0.0, 0.0));
+        assert_eq!(camera.get_pixel_spread_angle(), 0.0);
+
+        let camera = load_camera("type = \"fisheye\"\nfov = 180.0");
+        assert_direction(&camera.generate_ray(center.0, center.1), [0.0, 0.0, -1.0]);
+        assert_direction(&camera.generate_ray(left.0, left.1), [-1.0, 0.0, 0.0]);
+
+        let camera = load_camera("type = \"equirectangular\"\nshutter = [0.5, 0.5]");
+        assert_direction(&camera.generate_ray(center.0, center.1), [0.0, 0.0, -1.0]);
+        assert_direction(&camera.generate_ray(left.0, left.1), [0.0, 0.0, 1.0]);
+        assert_eq!(camera.generate_ray(center.0, center.1).get_time(), 0.5);
+    }
+
+    #[test]
+    fn t_lights() {
+        let light =
+            load_light("type = \"point\"\nposition = [1.0, 2.0, 3.0]\ncolor = [1.0, 0.5, 0.0]");
+        let position = get_point([1.0, 2.0, 3.0]);
+        assert_eq!(light.kind, LightType::Point { position });
+        assert_eq!(light.intensity, 2.0);
+        assert_eq!(light.color, Color::new(1.0, 0.5, 0.0));
+
+        let light = load_light("type = \"directional\"\ndirection = [0.0, -2.0, 0.0]");
+        let direction = get_vector([0.0, -1.0, 0.0]);
+        assert_eq!(light.kind, LightType::Directional { direction });
+
+        let light = load_light(
+            "type = \"spot\"\nposition = [0.0, 5.0, 0.0]\ndirection = [0.0, -1.0, 0.0]\n\
+             inner_angle = 20.0\nouter_angle = 30.0",
+        );
+        let (position, direction) = (get_point([0.0, 5.0, 0.0]), get_vector([0.0, -1.0, 0.0]));
+        assert_eq!(light.kind, Light::spot(position, direction, 20.0, 30.0, 2.0).kind);
+
+        let light = load_light(
+            "type = \"rectangle\"\ncorner = [0.0, 5.0, 0.0]\nedge_u = [1.0, 0.0, 0.0]\n\
+             edge_v = [0.0, 0.0, 1.0]\nsamples = 16",
+        );
+        let expected = LightType::Rectangle {
+            corner: get_point([0.0, 5.0, 0.0]),
+            edge_u: get_vector([1.0, 0.0, 0.0]),
+            edge_v: get_vector([0.0, 0.0, 1.0]),
+        };
+        assert_eq!(light.kind, expected);
+        assert_eq!(light.num_samples, 16);
+
+        let light = load_light("type = \"sphere\"\ncenter = [0.0, 5.0, 0.0]\nradius = 0.5");
+        let center = get_point([0.0, 5.0, 0.0]);
+        assert_eq!(light.kind, LightType::Sphere { center, radius: 0.5 });
+    }
+
+    #[test]
+    fn t_supersampling() {
+        let load_supersampler = |render: &str| {
+            let render = format!("{}\n{}", RENDER, render);
+            load(&get_scene(&render, CAMERA, "")).unwrap().settings.supersampler
+        };
+        assert_eq!(load_supersampler(""), Supersampler::new());
+
+        let supersampler = load_supersampler(
+            "pattern = { type = \"adaptive\", min_samples = 8, max_samples = 64, \
+             max_error = 0.01 }\n\
+             filter = { type = \"mitchell_netravali\", radius = 2.0 }",
+        );
+        let expected = Supersampler::new()
+            .pattern(SamplePattern::Adaptive { min_samples: 8, max_samples: 64, max_error: 0.01 })
+            .filter(PixelFilter::MitchellNetravali { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 });
+        assert_eq!(supersampler, expected);
+
+        let supersampler = load_supersampler(
+            "pattern = { type = \"halton\", num_samples = 32 }\n\
+             filter = { type = \"gaussian\", radius = 1.5, alpha = 2.0 }",
+        );
+        let expected = Supersampler::new()
+            .pattern(SamplePattern::Halton { num_samples: 32 })
+            .filter(PixelFilter::Gaussian { radius: 1.5, alpha: 2.0 });
+        assert_eq!(supersampler, expected);
+    }
+
+    #[test]
+    fn t_hdr_outputs() {
+        let settings = load(&get_scene(RENDER, CAMERA, "")).unwrap().settings;
+        assert_eq!((settings.exr_output, settings.hdr_output), (None, None));
+
+        let render = format!("{}\nexr_output = \"out.exr\"\nhdr_output = \"out.hdr\"", RENDER);
+        let settings = load(&get_scene(&render, CAMERA, "")).unwrap().settings;
+        assert_eq!(settings.exr_output, Some(PathBuf::from("out.exr")));
+        assert_eq!(settings.hdr_output, Some(PathBuf::from("out.hdr")));
+    }
+
+    #[test]
+    fn t_sphere_scale() {
+        let sphere = "[[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0";
+        let scaled = format!("{}\nscale = [2.0, 2.0, 2.0]", sphere);
+        let scene = load(&get_scene(RENDER, CAMERA, &scaled)).unwrap().scene;
+        let ray = Ray3d::from(get_point([0.0, 0.0, 10.0]), get_vector([0.0, 0.0, -1.0]));
+        let hit = scene.get_lbvh().traverse(&ray).unwrap();
+        assert!((hit.distance - 8.0).abs() < 1e-5);
+
+        for scale in ["scale = [1.0, 2.0, 1.0]", "end_scale = [2.0, 2.0, 3.0]"] {
+            let text = get_scene(RENDER, CAMERA, &format!("{}\n{}", sphere, scale));
+            assert!(matches!(load(&text).err().unwrap(), SceneFileError::Invalid(_)));
+        }
+    }
+
+    #[test]
+    fn t_missing_mesh() {
+        let mesh = "[[objects]]\ntype = \"mesh\"\npath = \"missing.obj\"";
+        let text = get_scene(RENDER, CAMERA, mesh);
+        match load(&text).err().unwrap() {
+            SceneFileError::Io(path, _) => {
+                assert_eq!(path, std::env::temp_dir().join("missing.obj"))
+            }
+            e => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn t_parse_errors() {
+        let sphere = "[[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0";
+        let halton = "pattern = { type = \"halton\", num_samples = 4, n = 4 }";
+        let halton = format!("{}\n{}", RENDER, halton);
+        let texts = [
+            // Unknown enum tags and values
+            get_scene(RENDER, CAMERA, "[[objects]]\ntype = \"cylinder\"\nradius = 1.0"),
+            get_scene(RENDER, "type = \"pinhole\"", ""),
+            get_scene(RENDER, CAMERA, "[[lights]]\ntype = \"area\"\nintensity = 1.0"),
+            get_scene("width = 2\nheight = 2\nshader = \"gouraud\"", CAMERA, ""),
+            get_scene("width = 2\nheight = 2\nfilter = { type = \"lanczos\" }", CAMERA, ""),
+            // Misspelled and missing fields
+            get_scene("width = 2\nheight = 2\nwidht = 3", CAMERA, ""),
+            get_scene(RENDER, CAMERA, &format!("{}\nmaterial = {{ albdeo = [1.0] }}", sphere)),
+            get_scene(&halton, CAMERA, ""),
+            get_scene(RENDER, "type = \"orthographic\"", ""),
+            get_scene("width = 2\nheight = 2\npattern = { type = \"sobol\", n = 4 }", CAMERA, ""),
+            "[render]\nwidth = 2\nheight = 2\noutput = \"out.png\"\n".to_string(),
+        ];
+        for text in texts {
+            assert!(matches!(load(&text).err().unwrap(), SceneFileError::Parse(_)), "{}", text);
+        }
+    }
+
+    #[test]
+    fn t_invalid_settings() {
+        let renders = [
+            "width = 0\nheight = 2",
+            "width = 2\nheight = 0",
+            "width = 2\nheight = 2\npattern = { type = \"jittered\", n = 0 }",
+            "width = 2\nheight = 2\nfilter = { type = \"box\", radius = 0.0 }",
+        ];
+        for render in renders {
+            let text = get_scene(render, CAMERA, "");
+            assert!(matches!(load(&text).err().unwrap(), SceneFileError::Invalid(_)), "{}", text);
+        }
+    }
+}